@@ -0,0 +1,67 @@
+//! A persistent cache for expensive build artifacts like encoded images.
+//!
+//! Entries are keyed by a hash of everything that went into producing them, so they
+//! never need to be invalidated, only cleaned up by deleting the directory.
+
+use color_eyre::{Result, eyre::WrapErr};
+use std::{
+    io,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// The user's cache directory, like `~/.cache/blogamer`. It is shared between all sites,
+/// as entries are keyed by their content.
+pub(crate) fn default_dir() -> PathBuf {
+    let env_dir = |name| {
+        std::env::var_os(name)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    };
+    let base = if cfg!(windows) {
+        env_dir("LOCALAPPDATA")
+    } else if cfg!(target_os = "macos") {
+        env_dir("HOME").map(|home| home.join("Library/Caches"))
+    } else {
+        env_dir("XDG_CACHE_HOME").or_else(|| env_dir("HOME").map(|home| home.join(".cache")))
+    };
+    base.unwrap_or_else(std::env::temp_dir).join("blogamer")
+}
+
+pub(crate) struct Cache {
+    dir: PathBuf,
+    /// Makes temporary file names unique, as entries may be inserted concurrently.
+    tmp_counter: AtomicU64,
+}
+
+impl Cache {
+    pub(crate) fn new(dir: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .wrap_err_with(|| format!("creating cache directory {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            tmp_counter: AtomicU64::new(0),
+        })
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.dir.join(key);
+        match std::fs::read(&path) {
+            Ok(content) => Ok(Some(content)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).wrap_err_with(|| format!("reading {}", path.display())),
+        }
+    }
+
+    pub(crate) fn insert(&self, key: &str, content: &[u8]) -> Result<()> {
+        // Write to a temporary file first so that an interrupted build doesn't leave a
+        // truncated entry behind.
+        let tmp = self.tmp_counter.fetch_add(1, Ordering::Relaxed);
+        let tmp_path = self
+            .dir
+            .join(format!("{key}.{}.{tmp}.tmp", std::process::id()));
+        std::fs::write(&tmp_path, content)
+            .wrap_err_with(|| format!("writing {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, self.dir.join(key)).wrap_err("renaming cache entry")
+    }
+}
@@ -0,0 +1,133 @@
+//! Site configuration, read from `blogamer.toml` in the input directory.
+
+use crate::{
+    Opts,
+    images::{ImageOverrides, ModernFormat},
+};
+use color_eyre::{Result, eyre::WrapErr};
+use std::io;
+
+const CONFIG_FILE: &str = "blogamer.toml";
+
+#[derive(serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct SiteConfig {
+    /// The name of the site, used as the title of the index page and the feeds.
+    pub(crate) title: String,
+    /// The author of the posts, defaults to the title.
+    pub(crate) author: Option<String>,
+    /// The absolute URL the site is hosted at. Overridden by `--base-url`.
+    pub(crate) base_url: Option<String>,
+    /// The text of the link back to the home page, defaults to the title.
+    pub(crate) nav_title: Option<String>,
+    /// The language of the site, for `<html lang>`.
+    pub(crate) language: String,
+    /// The name of a theme in `themes/` in the input directory, or the built-in `default`.
+    pub(crate) theme: String,
+    /// Add the files from `static/` to the content-hashed static files instead of copying
+    /// them into the root of the output. Templates reference them through `assets`.
+    pub(crate) fingerprint_static: bool,
+    pub(crate) images: ImageConfig,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ImageConfig {
+    /// The widths images are downscaled to for `srcset`, in addition to their original width.
+    pub(crate) widths: Vec<u32>,
+    /// The `sizes` attribute for images, describing how wide they are displayed.
+    pub(crate) sizes: String,
+    /// Whether to strip comments and whitespace from SVGs.
+    pub(crate) minify_svg: bool,
+    /// The formats generated in addition to JPEG or PNG with `--optimize`.
+    /// Overridden by `--formats`.
+    pub(crate) formats: Vec<ModernFormat>,
+    /// From 1 to 100.
+    pub(crate) jpeg_quality: u8,
+    /// From 1 to 100.
+    pub(crate) avif_quality: u8,
+    /// From 1 (slowest, smallest) to 10 (fastest).
+    pub(crate) avif_speed: u8,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            widths: vec![480, 960, 1440, 1920],
+            // Matches the width of `.main-content-inner` in the default theme.
+            sizes: "(min-width: 1300px) 50vw, (min-width: 700px) 70vw, 100vw".to_owned(),
+            minify_svg: false,
+            formats: vec![ModernFormat::Avif, ModernFormat::Webp],
+            jpeg_quality: 75,
+            avif_quality: 80,
+            avif_speed: 4,
+        }
+    }
+}
+
+impl Default for SiteConfig {
+    fn default() -> Self {
+        Self {
+            title: "Blog".to_owned(),
+            author: None,
+            base_url: None,
+            nav_title: None,
+            language: "en".to_owned(),
+            theme: crate::theme::DEFAULT.to_owned(),
+            fingerprint_static: false,
+            images: ImageConfig::default(),
+        }
+    }
+}
+
+impl SiteConfig {
+    /// Loads the config from the input directory, with the CLI options taking precedence.
+    pub(crate) fn load(opts: &Opts) -> Result<Self> {
+        let path = opts.input.join(CONFIG_FILE);
+        let mut config = match std::fs::read_to_string(&path) {
+            Ok(content) => toml::from_str::<SiteConfig>(&content)
+                .wrap_err_with(|| format!("invalid config in {}", path.display()))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => SiteConfig::default(),
+            Err(err) => {
+                return Err(err).wrap_err_with(|| format!("reading {}", path.display()));
+            }
+        };
+
+        if let Some(base_url) = &opts.base_url {
+            config.base_url = Some(base_url.clone());
+        }
+        if let Some(base_url) = &mut config.base_url {
+            base_url.truncate(base_url.trim_end_matches('/').len());
+        }
+
+        let images = &mut config.images;
+        match &opts.formats {
+            Some(formats) => images.formats = formats.clone(),
+            // Encoding the other formats is slow, only do it when asked to.
+            None if !opts.optimize => images.formats.clear(),
+            None => {}
+        }
+        if let Some(quality) = opts.jpeg_quality {
+            images.jpeg_quality = quality;
+        }
+        if let Some(quality) = opts.avif_quality {
+            images.avif_quality = quality;
+        }
+        if let Some(speed) = opts.avif_speed {
+            images.avif_speed = speed;
+        }
+        ImageOverrides::default()
+            .apply(images)
+            .wrap_err_with(|| format!("invalid image settings in {}", path.display()))?;
+
+        Ok(config)
+    }
+
+    pub(crate) fn author(&self) -> &str {
+        self.author.as_deref().unwrap_or(&self.title)
+    }
+
+    pub(crate) fn nav_title(&self) -> &str {
+        self.nav_title.as_deref().unwrap_or(&self.title)
+    }
+}
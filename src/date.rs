@@ -0,0 +1,139 @@
+//! Dates of posts, parsed from the frontmatter.
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, SecondsFormat};
+use color_eyre::{Result, eyre::bail};
+
+/// The date (and optionally time) a post was published at.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct PostDate {
+    datetime: DateTime<FixedOffset>,
+    has_time: bool,
+}
+
+impl PostDate {
+    /// Parses `YYYY-MM-DD`, `YYYY-MM-DD HH:MM[:SS]` (as UTC) or an RFC 3339 timestamp.
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+
+        if let Ok(datetime) = DateTime::parse_from_rfc3339(s) {
+            return Ok(Self {
+                datetime,
+                has_time: true,
+            });
+        }
+
+        for format in [
+            "%Y-%m-%dT%H:%M:%S",
+            "%Y-%m-%d %H:%M:%S",
+            "%Y-%m-%dT%H:%M",
+            "%Y-%m-%d %H:%M",
+        ] {
+            if let Ok(datetime) = NaiveDateTime::parse_from_str(s, format) {
+                return Ok(Self {
+                    datetime: datetime.and_utc().fixed_offset(),
+                    has_time: true,
+                });
+            }
+        }
+
+        if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            return Ok(Self {
+                datetime: date.and_hms_opt(0, 0, 0).unwrap().and_utc().fixed_offset(),
+                has_time: false,
+            });
+        }
+
+        bail!(
+            "invalid date `{s}`, expected `YYYY-MM-DD`, `YYYY-MM-DD HH:MM[:SS]` or an RFC 3339 timestamp"
+        )
+    }
+
+    /// `2025-06-10`, or `2025-06-10T12:00:00+02:00` if a time was given.
+    pub(crate) fn iso8601(&self) -> String {
+        if self.has_time {
+            self.datetime.format("%Y-%m-%dT%H:%M:%S%:z").to_string()
+        } else {
+            self.datetime.format("%Y-%m-%d").to_string()
+        }
+    }
+
+    /// `2025-06-10T00:00:00Z`, for feeds and templates.
+    pub(crate) fn rfc3339(&self) -> String {
+        self.datetime.to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
+
+    /// `Tue, 10 Jun 2025 00:00:00 +0000`, for RSS.
+    pub(crate) fn rfc2822(&self) -> String {
+        self.datetime.to_rfc2822()
+    }
+
+    /// `June 10, 2025`, for readers.
+    pub(crate) fn human(&self) -> String {
+        self.datetime.format("%B %-d, %Y").to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostDate;
+
+    #[test]
+    fn date_only() {
+        let date = PostDate::parse("2025-06-10").unwrap();
+        assert_eq!(date.iso8601(), "2025-06-10");
+        assert_eq!(date.rfc3339(), "2025-06-10T00:00:00Z");
+        assert_eq!(date.human(), "June 10, 2025");
+    }
+
+    #[test]
+    fn surrounding_whitespace() {
+        let date = PostDate::parse(" 2025-06-10\n").unwrap();
+        assert_eq!(date.iso8601(), "2025-06-10");
+    }
+
+    #[test]
+    fn naive_time_is_utc() {
+        for s in [
+            "2025-06-10 12:30",
+            "2025-06-10T12:30",
+            "2025-06-10 12:30:00",
+            "2025-06-10T12:30:00",
+        ] {
+            let date = PostDate::parse(s).unwrap();
+            assert_eq!(date.iso8601(), "2025-06-10T12:30:00+00:00", "{s}");
+            assert_eq!(date.rfc3339(), "2025-06-10T12:30:00Z", "{s}");
+        }
+    }
+
+    #[test]
+    fn rfc3339_keeps_offset() {
+        let date = PostDate::parse("2025-06-10T12:30:00+02:00").unwrap();
+        assert_eq!(date.iso8601(), "2025-06-10T12:30:00+02:00");
+        assert_eq!(date.rfc3339(), "2025-06-10T12:30:00+02:00");
+        assert_eq!(date.rfc2822(), "Tue, 10 Jun 2025 12:30:00 +0200");
+    }
+
+    #[test]
+    fn ordering() {
+        let date_only = PostDate::parse("2025-06-10").unwrap();
+        let later = PostDate::parse("2025-06-10 08:00").unwrap();
+        let earlier_offset = PostDate::parse("2025-06-10T09:00:00+02:00").unwrap();
+        assert!(date_only < later);
+        assert!(earlier_offset < later);
+    }
+
+    #[test]
+    fn invalid() {
+        for s in [
+            "",
+            "tomorrow",
+            "2025-13-01",
+            "2025-02-30",
+            "10.06.2025",
+            "2025-06-10 25:00",
+            "2025-06-10T12:30:00+25:00",
+        ] {
+            assert!(PostDate::parse(s).is_err(), "{s:?} should be invalid");
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Atom and RSS feeds for the blog posts.
+
+use crate::{Post, RenderedBody, SiteConfig, links};
+use askama::Template;
+use color_eyre::{Result, eyre::WrapErr};
+
+struct FeedEntry<'a> {
+    title: &'a str,
+    url: String,
+    updated: String,
+    published: String,
+    /// The publication date in RFC 2822 format, for RSS.
+    pub_date: String,
+    content: String,
+}
+
+fn entries<'a>(
+    base_url: &str,
+    posts: &'a [Post],
+    bodies: &'a [RenderedBody],
+) -> Vec<FeedEntry<'a>> {
+    posts
+        .iter()
+        .zip(bodies)
+        .map(|(post, body)| {
+            let path = format!("/blog/posts/{}/", post.name);
+            FeedEntry {
+                title: &post.frontmatter.common.title,
+                url: format!("{base_url}{path}"),
+                updated: post.last_modified().rfc3339(),
+                published: post.date.rfc3339(),
+                pub_date: post.date.rfc2822(),
+                content: absolute_urls(&body.html, &path, base_url),
+            }
+        })
+        .collect()
+}
+
+/// Makes the URLs of links and images absolute. RSS has no `xml:base`, so readers can't
+/// resolve them relative to the post.
+fn absolute_urls(html: &str, page: &str, base_url: &str) -> String {
+    let absolute = |url: &str| {
+        if url.is_empty() || url.starts_with('#') || links::is_external(url) {
+            url.to_owned()
+        } else {
+            format!("{base_url}{}", links::resolve_url(page, url))
+        }
+    };
+
+    let mut result = String::with_capacity(html.len());
+    let mut rest = html;
+    // Text is escaped, so `="` only appears in attributes.
+    while let Some(start) = rest.find("=\"") {
+        let (before, after) = rest.split_at(start + 2);
+        let end = after.find('"').unwrap_or(after.len());
+        let (value, after) = after.split_at(end);
+        result.push_str(before);
+
+        let attribute = before[..start]
+            .rsplit(|c: char| c.is_ascii_whitespace())
+            .next()
+            .unwrap_or_default();
+        match attribute {
+            "href" | "src" => result.push_str(&absolute(value)),
+            "srcset" => {
+                let candidates =
+                    value
+                        .split(", ")
+                        .map(|candidate| match candidate.split_once(' ') {
+                            Some((url, descriptor)) => format!("{} {descriptor}", absolute(url)),
+                            None => absolute(candidate),
+                        });
+                result.push_str(&candidates.collect::<Vec<_>>().join(", "));
+            }
+            _ => result.push_str(value),
+        }
+        rest = after;
+    }
+    result.push_str(rest);
+    result
+}
+
+pub(crate) fn render_atom(
+    site: &SiteConfig,
+    base_url: &str,
+    posts: &[Post],
+    bodies: &[RenderedBody],
+) -> Result<String> {
+    #[derive(askama::Template)]
+    #[template(path = "../templates/atom.xml")]
+    struct AtomTemplate<'a> {
+        title: &'a str,
+        author: &'a str,
+        base_url: &'a str,
+        updated: &'a str,
+        entries: &'a [FeedEntry<'a>],
+    }
+
+    let entries = entries(base_url, posts, bodies);
+    let updated = posts
+        .iter()
+        .map(Post::last_modified)
+        .max()
+        .map_or_else(|| "1970-01-01T00:00:00Z".to_owned(), |date| date.rfc3339());
+
+    AtomTemplate {
+        title: &site.title,
+        author: site.author(),
+        base_url,
+        updated: &updated,
+        entries: &entries,
+    }
+    .render()
+    .wrap_err("failed to render atom template")
+}
+
+pub(crate) fn render_rss(
+    site: &SiteConfig,
+    base_url: &str,
+    posts: &[Post],
+    bodies: &[RenderedBody],
+) -> Result<String> {
+    #[derive(askama::Template)]
+    #[template(path = "../templates/rss.xml")]
+    struct RssTemplate<'a> {
+        title: &'a str,
+        language: &'a str,
+        base_url: &'a str,
+        entries: &'a [FeedEntry<'a>],
+    }
+
+    let entries = entries(base_url, posts, bodies);
+
+    RssTemplate {
+        title: &site.title,
+        language: &site.language,
+        base_url,
+        entries: &entries,
+    }
+    .render()
+    .wrap_err("failed to render rss template")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::absolute_urls;
+
+    #[test]
+    fn absolute() {
+        let html = concat!(
+            r#"<p><a href="/blog/posts/b/">b</a> <a href="../c/#x">c</a> "#,
+            r##"<a href="#top">top</a> <a href="https://example.com">ext</a> a="b"</p>"##,
+            r#"<img src="/static/a-100.jpg" srcset="/static/a-50.jpg 50w, /static/a-100.jpg 100w" "#,
+            r#"style="background-image: url('data:image/jpeg;base64,AA==')">"#,
+        );
+        assert_eq!(
+            absolute_urls(html, "/blog/posts/a/", "https://blog.example"),
+            concat!(
+                r#"<p><a href="https://blog.example/blog/posts/b/">b</a> "#,
+                r#"<a href="https://blog.example/blog/posts/c/#x">c</a> "#,
+                r##"<a href="#top">top</a> <a href="https://example.com">ext</a> a="b"</p>"##,
+                r#"<img src="https://blog.example/static/a-100.jpg" "#,
+                r#"srcset="https://blog.example/static/a-50.jpg 50w, https://blog.example/static/a-100.jpg 100w" "#,
+                r#"style="background-image: url('data:image/jpeg;base64,AA==')">"#,
+            )
+        );
+    }
+}
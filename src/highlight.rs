@@ -0,0 +1,47 @@
+//! Syntax highlighting for code blocks, done at build time using CSS classes.
+
+use color_eyre::{Result, eyre::WrapErr};
+use std::sync::LazyLock;
+use syntect::{
+    highlighting::ThemeSet,
+    html::{ClassStyle, ClassedHTMLGenerator, css_for_theme_with_class_style},
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// The class for the `<pre>` of highlighted code, gets the theme's colors.
+pub(crate) const CODE_CLASS: &str = "hl-code";
+
+static SYNTAX_SET: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+
+/// Highlights the code, returning `None` if the language is not known.
+pub(crate) fn highlight(lang: &str, code: &str) -> Result<Option<String>> {
+    let Some(syntax) = SYNTAX_SET.find_syntax_by_token(lang) else {
+        return Ok(None);
+    };
+
+    let mut generator =
+        ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAX_SET, CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        generator
+            .parse_html_for_line_which_includes_newline(line)
+            .wrap_err("highlighting code")?;
+    }
+
+    Ok(Some(generator.finalize()))
+}
+
+/// The stylesheet for the highlighting classes, with a dark theme for dark mode.
+pub(crate) fn theme_css() -> Result<String> {
+    let themes = ThemeSet::load_defaults();
+    let light = css_for_theme_with_class_style(&themes.themes["InspiredGitHub"], CLASS_STYLE)
+        .wrap_err("generating light highlighting theme")?;
+    let dark = css_for_theme_with_class_style(&themes.themes["base16-ocean.dark"], CLASS_STYLE)
+        .wrap_err("generating dark highlighting theme")?;
+
+    Ok(format!(
+        "{light}\n@media (prefers-color-scheme: dark) {{\n{dark}\n}}\n"
+    ))
+}
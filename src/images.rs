@@ -0,0 +1,442 @@
+//! Detecting images that must not be re-encoded, and encoding the others.
+
+use crate::config::ImageConfig;
+use color_eyre::{
+    Result,
+    eyre::{WrapErr, ensure},
+};
+use image::{AnimationDecoder, DynamicImage, ImageFormat, codecs};
+use std::{io, path::Path};
+
+/// Formats generated in addition to the JPEG or PNG fallback.
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ModernFormat {
+    Avif,
+    Webp,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum OutputFormat {
+    Jpeg,
+    Png,
+    Avif,
+    Webp,
+}
+
+impl From<ModernFormat> for OutputFormat {
+    fn from(format: ModernFormat) -> Self {
+        match format {
+            ModernFormat::Avif => Self::Avif,
+            ModernFormat::Webp => Self::Webp,
+        }
+    }
+}
+
+impl OutputFormat {
+    pub(crate) fn ext(self) -> &'static str {
+        match self {
+            Self::Jpeg => ".jpg",
+            Self::Png => ".png",
+            Self::Avif => ".avif",
+            Self::Webp => ".webp",
+        }
+    }
+
+    pub(crate) fn media_type(self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::Avif => "image/avif",
+            Self::Webp => "image/webp",
+        }
+    }
+}
+
+/// Encoding settings for a single image, overriding the ones from the config.
+///
+/// Set in the frontmatter under `images`, keyed by the path used in the markdown, or
+/// at the end of the image title as the fields of a TOML inline table after `blogamer`,
+/// like `![alt](photo.jpg "A photo {blogamer avif_quality = 60}")`.
+#[derive(Clone, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ImageOverrides {
+    formats: Option<Vec<ModernFormat>>,
+    jpeg_quality: Option<u8>,
+    avif_quality: Option<u8>,
+    avif_speed: Option<u8>,
+}
+
+impl ImageOverrides {
+    /// Splits the overrides off the end of an image title, returning the rest of the title.
+    /// Titles without the `{blogamer ...}` marker are returned unchanged, they can contain
+    /// braces of their own.
+    pub(crate) fn from_title(title: &str) -> Result<(&str, Self)> {
+        #[derive(serde::Deserialize)]
+        struct Wrapper {
+            overrides: ImageOverrides,
+        }
+
+        const MARKER: &str = "{blogamer";
+
+        let trimmed = title.trim_end();
+        let Some(start) = trimmed.rfind(MARKER) else {
+            return Ok((title, Self::default()));
+        };
+        let Some(fields) = trimmed[start + MARKER.len()..].strip_suffix('}') else {
+            return Ok((title, Self::default()));
+        };
+        if !fields.is_empty() && !fields.starts_with(char::is_whitespace) {
+            return Ok((title, Self::default()));
+        }
+
+        let wrapper = toml::from_str::<Wrapper>(&format!("overrides = {{{fields}}}"))
+            .wrap_err("invalid image settings in title")?;
+        Ok((trimmed[..start].trim_end(), wrapper.overrides))
+    }
+
+    /// Fills in the settings that are not set here from `fallback`.
+    pub(crate) fn or(self, fallback: &Self) -> Self {
+        Self {
+            formats: self.formats.or_else(|| fallback.formats.clone()),
+            jpeg_quality: self.jpeg_quality.or(fallback.jpeg_quality),
+            avif_quality: self.avif_quality.or(fallback.avif_quality),
+            avif_speed: self.avif_speed.or(fallback.avif_speed),
+        }
+    }
+
+    pub(crate) fn apply(&self, config: &ImageConfig) -> Result<Encoding> {
+        let encoding = Encoding {
+            formats: self
+                .formats
+                .clone()
+                .unwrap_or_else(|| config.formats.clone()),
+            jpeg_quality: self.jpeg_quality.unwrap_or(config.jpeg_quality),
+            avif_quality: self.avif_quality.unwrap_or(config.avif_quality),
+            avif_speed: self.avif_speed.unwrap_or(config.avif_speed),
+        };
+        ensure!(
+            (1..=100).contains(&encoding.jpeg_quality),
+            "JPEG quality must be between 1 and 100"
+        );
+        ensure!(
+            (1..=100).contains(&encoding.avif_quality),
+            "AVIF quality must be between 1 and 100"
+        );
+        ensure!(
+            (1..=10).contains(&encoding.avif_speed),
+            "AVIF speed must be between 1 and 10"
+        );
+        Ok(encoding)
+    }
+}
+
+/// The settings an image is encoded with.
+pub(crate) struct Encoding {
+    pub(crate) formats: Vec<ModernFormat>,
+    jpeg_quality: u8,
+    avif_quality: u8,
+    avif_speed: u8,
+}
+
+impl Encoding {
+    /// The settings that affect the output of `format`, to be included in cache keys.
+    pub(crate) fn cache_key(&self, format: OutputFormat) -> String {
+        match format {
+            OutputFormat::Jpeg => format!("q{}", self.jpeg_quality),
+            OutputFormat::Avif => format!("q{}s{}", self.avif_quality, self.avif_speed),
+            OutputFormat::Png | OutputFormat::Webp => String::new(),
+        }
+    }
+
+    pub(crate) fn encode(&self, image: &DynamicImage, format: OutputFormat) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        // Only PNG supports every color type, convert to 8-bit RGB(A) for the others.
+        let converted;
+        let image = match format {
+            OutputFormat::Png => image,
+            _ => {
+                converted = if image.color().has_alpha() {
+                    DynamicImage::ImageRgba8(image.to_rgba8())
+                } else {
+                    DynamicImage::ImageRgb8(image.to_rgb8())
+                };
+                &converted
+            }
+        };
+        match format {
+            OutputFormat::Jpeg => image.write_with_encoder(
+                codecs::jpeg::JpegEncoder::new_with_quality(&mut bytes, self.jpeg_quality),
+            )?,
+            OutputFormat::Png => {
+                image.write_with_encoder(codecs::png::PngEncoder::new(&mut bytes))?
+            }
+            OutputFormat::Avif => {
+                image.write_with_encoder(codecs::avif::AvifEncoder::new_with_speed_quality(
+                    &mut bytes,
+                    self.avif_speed,
+                    self.avif_quality,
+                ))?
+            }
+            // The WebP encoder only supports lossless compression.
+            OutputFormat::Webp => {
+                image.write_with_encoder(codecs::webp::WebPEncoder::new_lossless(&mut bytes))?
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+pub(crate) fn is_svg(path: &Path, source: &[u8]) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("svg"))
+        || source.trim_ascii_start().starts_with(b"<svg")
+}
+
+/// Whether the image has more than one frame. Re-encoding would only keep the first one.
+pub(crate) fn is_animated(format: ImageFormat, source: &[u8]) -> Result<bool> {
+    let reader = io::Cursor::new(source);
+    let animated = match format {
+        ImageFormat::Gif => {
+            let decoder = codecs::gif::GifDecoder::new(reader)?;
+            decoder.into_frames().take(2).count() > 1
+        }
+        ImageFormat::Png => codecs::png::PngDecoder::new(reader)?.is_apng()?,
+        ImageFormat::WebP => codecs::webp::WebPDecoder::new(reader)?.has_animation(),
+        _ => false,
+    };
+    Ok(animated)
+}
+
+/// A tiny, low quality JPEG of the image that the browser scales up while the image loads.
+pub(crate) fn placeholder(image: &DynamicImage) -> Result<Vec<u8>> {
+    let thumbnail = image.thumbnail(16, 16).to_rgb8();
+    let mut bytes = vec![];
+    thumbnail.write_with_encoder(codecs::jpeg::JpegEncoder::new_with_quality(&mut bytes, 50))?;
+    Ok(bytes)
+}
+
+/// Removes comments and collapses whitespace. Whitespace between elements is removed, except
+/// inside `<text>` where it separates words. CDATA sections and the contents of `<style>` and
+/// `<script>` are kept as they are.
+pub(crate) fn minify_svg(source: Vec<u8>) -> Result<Vec<u8>> {
+    let svg = String::from_utf8(source).wrap_err("SVG is not valid UTF-8")?;
+    // Whitespace can't be touched anywhere if some of it must be preserved.
+    let preserve_space =
+        svg.contains("xml:space=\"preserve\"") || svg.contains("xml:space='preserve'");
+
+    let mut minified = String::with_capacity(svg.len());
+    // How many `<text>` elements the current position is in.
+    let mut text_depth = 0_usize;
+    let mut rest = svg.as_str();
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment
+                .find("-->")
+                .map_or("", |end| &comment[end + "-->".len()..]);
+        } else if rest.starts_with("<![CDATA[") {
+            let end = rest.find("]]>").map_or(rest.len(), |end| end + "]]>".len());
+            minified.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if rest.starts_with('<') {
+            let end = tag_end(rest);
+            let tag = &rest[..end];
+            minified.push_str(tag);
+            rest = &rest[end..];
+
+            let closing = tag.starts_with("</");
+            let self_closing = tag.ends_with("/>");
+            let name = tag
+                .trim_start_matches(['<', '/'])
+                .split(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/')
+                .next()
+                .unwrap_or_default();
+            match name {
+                "style" | "script" if !closing && !self_closing => {
+                    let end = rest.find(&format!("</{name}")).unwrap_or(rest.len());
+                    minified.push_str(&rest[..end]);
+                    rest = &rest[end..];
+                }
+                "text" if closing => text_depth = text_depth.saturating_sub(1),
+                "text" if !self_closing => text_depth += 1,
+                _ => {}
+            }
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = &rest[..end];
+            rest = &rest[end..];
+
+            if preserve_space {
+                minified.push_str(text);
+            } else if !text.trim().is_empty() || text_depth > 0 {
+                let mut in_whitespace = false;
+                for c in text.chars() {
+                    if c.is_whitespace() {
+                        if !in_whitespace {
+                            minified.push(' ');
+                        }
+                        in_whitespace = true;
+                    } else {
+                        minified.push(c);
+                        in_whitespace = false;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(minified.trim().as_bytes().to_owned())
+}
+
+/// The length of the tag at the start of `s`, which may contain `>` in quoted attributes.
+fn tag_end(s: &str) -> usize {
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            (None, '>') => return i + 1,
+            _ => {}
+        }
+    }
+    s.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImageOverrides, ModernFormat, is_animated, minify_svg};
+    use image::{
+        Delay, Frame, ImageFormat, RgbaImage,
+        codecs::{gif::GifEncoder, png::PngEncoder},
+    };
+
+    fn minify(svg: &str) -> String {
+        String::from_utf8(minify_svg(svg.as_bytes().to_owned()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn minify_removes_whitespace_between_elements() {
+        let svg = "<svg>\n  <!-- a circle -->\n  <circle r=\"1\" />\n  <g>\n    <rect/>\n  </g>\n</svg>\n";
+        assert_eq!(minify(svg), r#"<svg><circle r="1" /><g><rect/></g></svg>"#);
+    }
+
+    #[test]
+    fn minify_keeps_spaces_in_text() {
+        let svg = "<svg>\n  <text x=\"0\">\n    <tspan>a</tspan> <tspan>b</tspan>\n    and   more\n  </text>\n</svg>";
+        assert_eq!(
+            minify(svg),
+            r#"<svg><text x="0"> <tspan>a</tspan> <tspan>b</tspan> and more </text></svg>"#
+        );
+    }
+
+    #[test]
+    fn minify_keeps_raw_content() {
+        let svg = concat!(
+            "<svg>\n",
+            "  <style>\n    /* <!-- not a comment --> */\n    a > b { fill: red }\n  </style>\n",
+            "  <script><![CDATA[ if (a < b) {} ]]></script>\n",
+            "  <desc><![CDATA[  <!-- kept -->  ]]></desc>\n",
+            "  <path d=\"M 0 0\" data-x=\"a > b\"/>\n",
+            "</svg>",
+        );
+        assert_eq!(
+            minify(svg),
+            concat!(
+                "<svg>",
+                "<style>\n    /* <!-- not a comment --> */\n    a > b { fill: red }\n  </style>",
+                "<script><![CDATA[ if (a < b) {} ]]></script>",
+                "<desc><![CDATA[  <!-- kept -->  ]]></desc>",
+                r#"<path d="M 0 0" data-x="a > b"/>"#,
+                "</svg>",
+            )
+        );
+    }
+
+    #[test]
+    fn minify_preserved_space() {
+        let svg = "<svg xml:space=\"preserve\">\n  <text>a  b</text>\n</svg>";
+        assert_eq!(
+            minify(svg),
+            "<svg xml:space=\"preserve\">\n  <text>a  b</text>\n</svg>"
+        );
+    }
+
+    fn gif(frames: usize) -> Vec<u8> {
+        let mut bytes = vec![];
+        {
+            let mut encoder = GifEncoder::new(&mut bytes);
+            for _ in 0..frames {
+                let frame = Frame::from_parts(
+                    RgbaImage::new(2, 2),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(100, 1),
+                );
+                encoder.encode_frame(frame).unwrap();
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn animated() {
+        assert!(!is_animated(ImageFormat::Gif, &gif(1)).unwrap());
+        assert!(is_animated(ImageFormat::Gif, &gif(2)).unwrap());
+
+        let mut png = vec![];
+        RgbaImage::new(2, 2)
+            .write_with_encoder(PngEncoder::new(&mut png))
+            .unwrap();
+        assert!(!is_animated(ImageFormat::Png, &png).unwrap());
+        assert!(!is_animated(ImageFormat::Jpeg, b"not even read").unwrap());
+    }
+
+    fn from_title(title: &str) -> (&str, ImageOverrides) {
+        ImageOverrides::from_title(title).unwrap()
+    }
+
+    #[test]
+    fn title_without_settings() {
+        for title in [
+            "",
+            "A cat",
+            "The set {1, 2}",
+            "{avif_quality = 60}",
+            "{blogamerx}",
+        ] {
+            let (caption, overrides) = from_title(title);
+            assert_eq!(caption, title);
+            assert!(overrides.avif_quality.is_none(), "{title}");
+        }
+    }
+
+    #[test]
+    fn title_with_settings() {
+        let (caption, overrides) = from_title("A cat {blogamer}");
+        assert_eq!(caption, "A cat");
+        assert!(overrides.avif_quality.is_none());
+
+        let (caption, overrides) = from_title("The set {1, 2} {blogamer avif_quality = 60}  ");
+        assert_eq!(caption, "The set {1, 2}");
+        assert_eq!(overrides.avif_quality, Some(60));
+
+        let (caption, overrides) =
+            from_title("{blogamer formats = [\"webp\"], jpeg_quality = 90, avif_speed = 8}");
+        assert_eq!(caption, "");
+        assert!(overrides.formats == Some(vec![ModernFormat::Webp]));
+        assert_eq!(overrides.jpeg_quality, Some(90));
+        assert_eq!(overrides.avif_speed, Some(8));
+    }
+
+    #[test]
+    fn title_with_invalid_settings() {
+        for title in [
+            "A cat {blogamer avif_quality = }",
+            "A cat {blogamer avif_quality = \"high\"}",
+            "A cat {blogamer unknown = 1}",
+            "A cat {blogamer avif_quality = 60 jpeg_quality = 50}",
+        ] {
+            assert!(ImageOverrides::from_title(title).is_err(), "{title}");
+        }
+    }
+}
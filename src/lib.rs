@@ -1,57 +1,292 @@
 use askama::Template;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use cache::Cache;
 use color_eyre::{
     Result,
-    eyre::{OptionExt, WrapErr, bail, ensure},
+    eyre::{OptionExt, WrapErr, bail, ensure, eyre},
 };
-use pulldown_cmark::{Event, Options, Tag, TagEnd};
+use config::SiteConfig;
+use date::PostDate;
+use image::ImageDecoder;
+use images::{ImageOverrides, OutputFormat};
+use pulldown_cmark::{CodeBlockKind, Event, Options, Tag, TagEnd};
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use sha2::Digest;
+use sitemap::SitemapEntry;
 use std::{
-    collections::HashMap,
+    cmp::Reverse,
+    collections::BTreeMap,
     fs::DirEntry,
     io,
     path::{Path, PathBuf},
+    sync::Mutex,
 };
+use templates::{DateContext, PostSummary, SiteContext, Templates};
+
+mod cache;
+mod config;
+mod date;
+mod feed;
+mod highlight;
+mod images;
+mod links;
+mod pages;
+mod serve;
+mod sitemap;
+mod static_files;
+mod tags;
+mod templates;
+mod theme;
+mod write;
 
 #[derive(clap::Parser)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Build the site into the output directory.
+    Build {
+        #[clap(flatten)]
+        opts: Opts,
+        /// Include posts and pages marked with `draft: true`.
+        #[clap(long)]
+        drafts: bool,
+        #[clap(long, short)]
+        output: PathBuf,
+    },
+    /// Serve the site on localhost, rebuilding it whenever the input changes.
+    Serve {
+        #[clap(flatten)]
+        opts: Opts,
+        /// Include posts and pages marked with `draft: true`.
+        #[clap(long)]
+        drafts: bool,
+        #[clap(long, default_value_t = 8000)]
+        port: u16,
+    },
+    /// Create a new draft post.
+    New {
+        #[clap(long, short)]
+        input: PathBuf,
+        /// The name of the post, used in its URL.
+        slug: String,
+        /// Create the post as a directory with an `index.md`, for posts with images.
+        #[clap(long)]
+        dir: bool,
+    },
+    /// Validate all posts, including drafts, without writing any output.
+    Check {
+        #[clap(flatten)]
+        opts: Opts,
+    },
+    /// Remove the output directory.
+    Clean {
+        #[clap(long, short)]
+        output: PathBuf,
+    },
+}
+
+#[derive(clap::Args, Clone)]
 pub struct Opts {
+    /// Also generate the image formats configured in `blogamer.toml`, AVIF and WebP by default.
     #[clap(long)]
     optimize: bool,
+    /// The image formats to generate in addition to JPEG or PNG, e.g. `avif,webp`.
+    /// Implies `--optimize` with these formats.
+    #[clap(long, value_delimiter = ',')]
+    formats: Option<Vec<images::ModernFormat>>,
+    /// JPEG quality from 1 to 100, overrides `blogamer.toml`.
+    #[clap(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    jpeg_quality: Option<u8>,
+    /// AVIF quality from 1 to 100, overrides `blogamer.toml`.
+    #[clap(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    avif_quality: Option<u8>,
+    /// AVIF encoding speed from 1 (slowest, smallest) to 10, overrides `blogamer.toml`.
+    #[clap(long, value_parser = clap::value_parser!(u8).range(1..=10))]
+    avif_speed: Option<u8>,
     #[clap(long, short)]
     input: PathBuf,
-    #[clap(long, short)]
-    output: PathBuf,
+    /// The absolute URL the site is hosted at, e.g. `https://example.com`.
+    /// Required for generating feeds. Overrides `base_url` from `blogamer.toml`.
+    #[clap(long)]
+    base_url: Option<String>,
+    /// Where to cache encoded images between builds, defaults to `blogamer` in the user's
+    /// cache directory, like `~/.cache/blogamer`.
+    #[clap(long)]
+    cache_dir: Option<PathBuf>,
+}
+
+impl Opts {
+    fn cache_dir(&self) -> PathBuf {
+        self.cache_dir.clone().unwrap_or_else(cache::default_dir)
+    }
+}
+
+pub fn run(cli: Cli) -> Result<()> {
+    match cli.command {
+        Command::Build {
+            opts,
+            drafts,
+            output,
+        } => generate(opts, drafts, &output),
+        Command::Serve { opts, drafts, port } => serve::serve(opts, drafts, port),
+        Command::New { input, slug, dir } => new_post(&input, &slug, dir),
+        Command::Check { opts } => check(opts),
+        Command::Clean { output } => clean(&output),
+    }
 }
 
 pub struct Context {
     opts: Opts,
-    static_files: HashMap<String, Vec<u8>>,
-    theme_css_path: String,
+    site: SiteConfig,
+    cache: Cache,
+    templates: Templates,
+    static_files: Mutex<BTreeMap<String, Vec<u8>>>,
+    /// Paths of the stylesheets included in every page.
+    stylesheets: Vec<String>,
 }
 
 struct PictureImages {
     sources: Vec<PictureSource>,
-    fallback_path: String,
-    height: u32,
-    width: u32,
+    fallback: PictureSource,
+    /// The width and height, not known for vector images.
+    dimensions: Option<(u32, u32)>,
+    /// A tiny blurry version as a `data:` URL, shown until the image has loaded.
+    placeholder: Option<String>,
+}
+
+impl PictureImages {
+    /// An image that is used as it is, without other formats or sizes.
+    fn passthrough(path: String, media_type: &str, dimensions: Option<(u32, u32)>) -> Self {
+        Self {
+            sources: vec![],
+            fallback: PictureSource {
+                variants: vec![ImageVariant {
+                    path,
+                    width: dimensions.map_or(0, |(width, _)| width),
+                }],
+                media_type: media_type.to_owned(),
+            },
+            dimensions,
+            placeholder: None,
+        }
+    }
 }
 
 struct PictureSource {
-    path: String,
+    /// Sorted by width, the last one has the original size.
+    variants: Vec<ImageVariant>,
     media_type: String,
 }
 
+struct ImageVariant {
+    path: String,
+    width: u32,
+}
+
+impl PictureSource {
+    fn srcset(&self) -> String {
+        self.variants
+            .iter()
+            .map(|variant| format!("{} {}w", variant.path, variant.width))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn largest(&self) -> &ImageVariant {
+        self.variants.last().unwrap()
+    }
+}
+
 impl Context {
-    fn add_static_file(&mut self, name: &str, ext: &str, content: Vec<u8>) -> Result<String> {
+    fn new(opts: Opts) -> Result<Self> {
+        let site = SiteConfig::load(&opts).wrap_err("loading site config")?;
+        let cache = Cache::new(opts.cache_dir())?;
+        let theme_dir = theme::theme_dir(&opts.input, &site.theme)?;
+        // Single templates in the input directory take precedence over the theme.
+        let mut template_dirs = vec![opts.input.join("templates")];
+        template_dirs.extend(theme_dir.clone());
+        let templates = Templates::new(template_dirs);
+        let mut ctx = Context {
+            opts,
+            site,
+            cache,
+            templates,
+            static_files: Default::default(),
+            stylesheets: vec![],
+        };
+
+        let mut assets = match &theme_dir {
+            Some(dir) => theme::add_assets(&ctx, dir).wrap_err("adding theme assets")?,
+            None => BTreeMap::new(),
+        };
+        if ctx.site.fingerprint_static
+            && let Some(dir) = static_files::find_dir(&ctx.opts.input)?
+        {
+            // The site's own files take precedence over the theme's.
+            assets.extend(static_files::fingerprint(&ctx, &dir).wrap_err("adding static files")?);
+        }
+        ctx.templates.add_global("assets", assets);
+
+        let theme_css = match ctx.templates.asset("theme.css")? {
+            (css, Some(dir)) => theme::rewrite_css_urls(&ctx, &css, &dir)?,
+            (css, None) => css,
+        };
+        let theme_css_path = ctx
+            .add_static_file("theme", ".css", theme_css.into_bytes())
+            .wrap_err("adding theme.css")?;
+        let highlight_css_path = ctx
+            .add_static_file("highlight", ".css", highlight::theme_css()?.into_bytes())
+            .wrap_err("adding highlight.css")?;
+        ctx.stylesheets = vec![theme_css_path, highlight_css_path];
+
+        Ok(ctx)
+    }
+
+    fn add_static_file(&self, name: &str, ext: &str, content: Vec<u8>) -> Result<String> {
         let name = format!("{name}-{}{ext}", create_hash_string(&content));
-        let _ = self.static_files.insert(name.clone(), content);
+        let _ = self
+            .static_files
+            .lock()
+            .unwrap()
+            .insert(name.clone(), content);
         Ok(format!("/static/{name}"))
     }
 
-    fn add_image(&mut self, path: &Path) -> Result<PictureImages> {
-        let image = image::ImageReader::open(path)
-            .wrap_err("reading image")?
-            .decode()
-            .wrap_err("decoding image")?;
+    /// Copies a local file referenced by a link into the static files, returning the new URL.
+    /// Returns `None` for links that don't point to local files, like external links or other
+    /// posts.
+    fn add_linked_file(&self, relative_to: &Path, dest: &str) -> Result<Option<String>> {
+        let Some((path, suffix)) = links::linked_file(relative_to, dest) else {
+            return Ok(None);
+        };
+
+        let url = self.add_file(&path)?;
+        Ok(Some(format!("{url}{suffix}")))
+    }
+
+    /// Adds a file to the static files under its own name and extension, returning the URL.
+    fn add_file(&self, path: &Path) -> Result<String> {
+        let content =
+            std::fs::read(path).wrap_err_with(|| format!("reading {}", path.display()))?;
+        let name = path
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .ok_or_eyre("file does not have a valid name")?;
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| format!(".{ext}"))
+            .unwrap_or_default();
+
+        self.add_static_file(name, &ext, content)
+    }
+
+    fn add_image(&self, path: &Path, overrides: &ImageOverrides) -> Result<PictureImages> {
+        let source = std::fs::read(path).wrap_err("reading image")?;
 
         let name = path
             .file_stem()
@@ -59,43 +294,195 @@ impl Context {
             .to_str()
             .unwrap();
 
-        let optimize = self.opts.optimize;
+        if images::is_svg(path, &source) {
+            let content = if self.site.images.minify_svg {
+                images::minify_svg(source).wrap_err("minifying SVG")?
+            } else {
+                source
+            };
+            let path = self.add_static_file(name, ".svg", content)?;
+            return Ok(PictureImages::passthrough(path, "image/svg+xml", None));
+        }
+
+        let source_hash = create_hash_string(&source);
+        let reader = || {
+            image::ImageReader::new(io::Cursor::new(source.as_slice()))
+                .with_guessed_format()
+                .wrap_err("detecting image format")
+        };
+        let format = reader()?.format().ok_or_eyre("unknown image format")?;
+        let (width, height) = reader()?
+            .into_dimensions()
+            .wrap_err("reading image dimensions")?;
+
+        if images::is_animated(format, &source).wrap_err("reading animation")? {
+            let ext = format!(".{}", format.extensions_str()[0]);
+            let path = self.add_static_file(name, &ext, source)?;
+            return Ok(PictureImages::passthrough(
+                path,
+                format.to_mime_type(),
+                Some((width, height)),
+            ));
+        }
 
-        let mut encode = |format, ext| -> Result<_> {
-            let mut bytes = vec![];
-            image.write_to(&mut io::Cursor::new(&mut bytes), format)?;
+        let encoding = overrides.apply(&self.site.images)?;
 
-            self.add_static_file(name, ext, bytes)
+        // JPEG doesn't support transparency.
+        let has_alpha = reader()?
+            .into_decoder()
+            .wrap_err("reading image header")?
+            .color_type()
+            .has_alpha();
+        let fallback = if has_alpha {
+            OutputFormat::Png
+        } else {
+            OutputFormat::Jpeg
         };
 
-        let fallback_path = encode(image::ImageFormat::Jpeg, ".jpg")?;
-
-        let sources = if optimize {
-            let avif_path = encode(image::ImageFormat::Avif, ".avif")?;
-            let webp_path = encode(image::ImageFormat::WebP, ".webp")?;
-            vec![
-                PictureSource {
-                    path: avif_path,
-                    media_type: "image/avif".to_owned(),
-                },
-                PictureSource {
-                    path: webp_path,
-                    media_type: "image/webp".to_owned(),
-                },
-            ]
+        // The first format is the fallback for browsers that don't support the others.
+        let mut formats = vec![fallback];
+        formats.extend(encoding.formats.iter().copied().map(OutputFormat::from));
+
+        // Never upscale, the original size is always the largest variant.
+        let mut widths = self
+            .site
+            .images
+            .widths
+            .iter()
+            .copied()
+            .filter(|&w| w > 0 && w < width)
+            .collect::<Vec<_>>();
+        widths.push(width);
+        widths.sort_unstable();
+        widths.dedup();
+
+        let jobs = formats
+            .iter()
+            .flat_map(|&format| widths.iter().map(move |&w| (format, w)))
+            .map(|(format, w)| -> Result<_> {
+                let key = create_hash_string(
+                    format!(
+                        "{source_hash}{}{}@{w}",
+                        format.ext(),
+                        encoding.cache_key(format)
+                    )
+                    .as_bytes(),
+                );
+                let bytes = self.cache.get(&key)?;
+                Ok((format, w, key, bytes))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // The placeholder would show through transparent parts of the image.
+        let placeholder_key = create_hash_string(format!("{source_hash}-placeholder").as_bytes());
+        let placeholder = if has_alpha {
+            None
+        } else {
+            Some(self.cache.get(&placeholder_key)?)
+        };
+
+        // Only decode the image if one of the encoded versions is not cached yet.
+        let image = if jobs.iter().any(|(_, _, _, bytes)| bytes.is_none())
+            || placeholder.as_ref().is_some_and(Option::is_none)
+        {
+            Some(reader()?.decode().wrap_err("decoding image")?)
         } else {
-            vec![]
+            None
+        };
+
+        let paths = jobs
+            .into_par_iter()
+            .map(|(format, w, key, bytes)| -> Result<_> {
+                let bytes = match bytes {
+                    Some(bytes) => bytes,
+                    None => {
+                        let image = image.as_ref().unwrap();
+                        let resized;
+                        let image = if w == width {
+                            image
+                        } else {
+                            let h = (u64::from(height) * u64::from(w) / u64::from(width)).max(1);
+                            resized = image.resize_exact(
+                                w,
+                                h as u32,
+                                image::imageops::FilterType::Lanczos3,
+                            );
+                            &resized
+                        };
+
+                        let bytes = encoding.encode(image, format).wrap_err_with(|| {
+                            format!("encoding image as {} at {w}px", format.ext())
+                        })?;
+                        self.cache.insert(&key, &bytes)?;
+                        bytes
+                    }
+                };
+
+                let path = self.add_static_file(&format!("{name}-{w}"), format.ext(), bytes)?;
+                Ok(ImageVariant { path, width: w })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut paths = paths.into_iter();
+        let mut sources = formats
+            .iter()
+            .map(|format| PictureSource {
+                variants: paths.by_ref().take(widths.len()).collect(),
+                media_type: format.media_type().to_owned(),
+            })
+            .collect::<Vec<_>>();
+        let fallback = sources.remove(0);
+
+        let placeholder = match placeholder {
+            None => None,
+            Some(Some(bytes)) => Some(bytes),
+            Some(None) => {
+                let bytes = images::placeholder(image.as_ref().unwrap())
+                    .wrap_err("generating placeholder")?;
+                self.cache.insert(&placeholder_key, &bytes)?;
+                Some(bytes)
+            }
         };
 
         Ok(PictureImages {
             sources,
-            fallback_path,
-            height: image.height(),
-            width: image.width(),
+            fallback,
+            dimensions: Some((width, height)),
+            placeholder: placeholder
+                .map(|bytes| format!("data:image/jpeg;base64,{}", BASE64_STANDARD.encode(bytes))),
         })
     }
 }
 
+/// Turns a string into something usable in URLs, lowercase alphanumerics separated by `-`.
+fn slugify(s: &str) -> String {
+    let mut slug = String::new();
+    for c in s.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(slug.trim_end_matches('-').len());
+    slug
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 fn create_hash_string(bytes: &[u8]) -> String {
     let digest = sha2::Sha256::digest(bytes);
     bs58::encode(&digest[..16]).into_string()
@@ -105,60 +492,285 @@ struct Post {
     name: String,
     relative_to: PathBuf,
     frontmatter: Frontmatter,
+    date: PostDate,
     body_md: String,
+    /// The line in the source file the body starts at, for error messages.
+    body_line: usize,
+    updated: Option<PostDate>,
 }
 
-mod write {
-    use color_eyre::{Result, eyre::Context};
-    use std::path::Path;
-
-    pub fn initialize(base: &Path) -> Result<()> {
-        let _ = std::fs::remove_dir_all(base).wrap_err("deleting previous output");
-        Ok(std::fs::create_dir_all(base).wrap_err("creating output")?)
+impl Post {
+    fn last_modified(&self) -> PostDate {
+        self.updated.unwrap_or(self.date)
     }
 }
 
-pub fn generate(opts: Opts) -> Result<()> {
-    let mut ctx = Context {
-        opts,
-        static_files: Default::default(),
-        theme_css_path: String::new(),
+pub fn generate(opts: Opts, drafts: bool, output: &Path) -> Result<()> {
+    let ctx = Context::new(opts)?;
+
+    let mut posts = collect_posts(&ctx.opts.input.join("posts"), drafts)
+        .wrap_err_with(|| format!("reading posts from {}", ctx.opts.input.display()))?;
+    // `read_dir` order is unspecified, keep posts from the same date in a stable order.
+    posts.sort_by(|a, b| a.name.cmp(&b.name));
+    posts.sort_by_key(|post| Reverse(post.date));
+
+    let pages = pages::collect_pages(&ctx.opts.input.join("pages"), drafts)
+        .wrap_err_with(|| format!("reading pages from {}", ctx.opts.input.display()))?;
+
+    let mut output = write::Output::new(output).wrap_err("initializing output")?;
+    let mut sitemap = vec![];
+    let newest = posts.iter().map(Post::last_modified).max();
+
+    let bodies = posts
+        .par_iter()
+        .map(|post| {
+            render_body(
+                &ctx,
+                &post.relative_to,
+                &post.body_md,
+                &post.frontmatter.common.images,
+            )
+            .wrap_err_with(|| format!("rendering post {}", post.name))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    for problem in links::check_links(&posts, &bodies) {
+        eprintln!("warning: {problem}");
+    }
+
+    for (post, body) in posts.iter().zip(&bodies) {
+        let html = render_post(&ctx, post, body)?;
+
+        output.write(
+            Path::new("blog/posts").join(&post.name).join("index.html"),
+            html.as_bytes(),
+        )?;
+        sitemap.push(SitemapEntry::new(
+            format!("/blog/posts/{}/", post.name),
+            Some(post.last_modified()),
+        ));
+    }
+
+    let page_bodies = pages
+        .par_iter()
+        .map(|page| pages::render_page_body(&ctx, page))
+        .collect::<Result<Vec<_>>>()?;
+    for (page, body) in pages.iter().zip(&page_bodies) {
+        let html = pages::render_page(&ctx, page, body)?;
+        output
+            .write(page.output_path(), html.as_bytes())
+            .wrap_err_with(|| format!("writing page /{}", page.path))?;
+        sitemap.push(SitemapEntry::new(page.url(), page.updated));
+    }
+
+    let index = render_index(&ctx, &posts)?;
+    output
+        .write("blog/index.html", index.as_bytes())
+        .wrap_err("writing blog index")?;
+    sitemap.push(SitemapEntry::new("/blog/".to_owned(), newest));
+    // A page can take the place of the blog index as the home page.
+    if !pages.iter().any(|page| page.path.is_empty()) {
+        output
+            .write("index.html", index.as_bytes())
+            .wrap_err("writing index")?;
+        sitemap.push(SitemapEntry::new("/".to_owned(), newest));
+    }
+
+    let tags = tags::collect_tags(&posts);
+    for tag in &tags {
+        let html = tags::render_tag(&ctx, tag)?;
+        output.write(
+            Path::new("blog/tags").join(&tag.slug).join("index.html"),
+            html.as_bytes(),
+        )?;
+        sitemap.push(SitemapEntry::new(
+            format!("/blog/tags/{}/", tag.slug),
+            tag.posts.iter().map(|post| post.last_modified()).max(),
+        ));
+    }
+    let html = tags::render_tags(&ctx, &tags)?;
+    output
+        .write("blog/tags/index.html", html.as_bytes())
+        .wrap_err("writing tags index")?;
+    sitemap.push(SitemapEntry::new("/blog/tags/".to_owned(), newest));
+
+    if let Some(base_url) = &ctx.site.base_url {
+        let atom = feed::render_atom(&ctx.site, base_url, &posts, &bodies)
+            .wrap_err("rendering atom feed")?;
+        output
+            .write("blog/feed.xml", atom.as_bytes())
+            .wrap_err("writing atom feed")?;
+        let rss = feed::render_rss(&ctx.site, base_url, &posts, &bodies)
+            .wrap_err("rendering rss feed")?;
+        output
+            .write("blog/rss.xml", rss.as_bytes())
+            .wrap_err("writing rss feed")?;
+    }
+
+    let static_dir = if ctx.site.fingerprint_static {
+        None
+    } else {
+        static_files::find_dir(&ctx.opts.input)?
     };
 
-    ctx.theme_css_path = ctx
-        .add_static_file(
-            "theme",
-            ".css",
-            include_bytes!("../templates/theme.css")
-                .as_slice()
-                .to_owned(),
-        )
-        .wrap_err("adding theme.css")?;
+    // Search engines need absolute URLs.
+    if let Some(base_url) = &ctx.site.base_url {
+        let xml = sitemap::render_sitemap(base_url, &sitemap).wrap_err("rendering sitemap")?;
+        output
+            .write("sitemap.xml", xml.as_bytes())
+            .wrap_err("writing sitemap")?;
+        // Don't collide with a hand-written one.
+        if !static_dir
+            .as_ref()
+            .is_some_and(|dir| dir.join("robots.txt").exists())
+        {
+            output
+                .write("robots.txt", sitemap::render_robots(base_url).as_bytes())
+                .wrap_err("writing robots.txt")?;
+        }
+    }
 
-    let posts = collect_posts(&ctx.opts.input.join("posts"))
-        .wrap_err_with(|| format!("reading posts from {}", ctx.opts.input.display()))?;
+    // Written last, so that collisions are reported for the copied files.
+    if let Some(dir) = static_dir {
+        for (relative, path) in static_files::files(&dir)? {
+            let content =
+                std::fs::read(&path).wrap_err_with(|| format!("reading {}", path.display()))?;
+            output
+                .write(&relative, &content)
+                .wrap_err_with(|| format!("copying static file {}", path.display()))?;
+        }
+    }
+
+    for (name, content) in ctx.static_files.into_inner().unwrap() {
+        output
+            .write(Path::new("static").join(name), &content)
+            .wrap_err("writing static file")?;
+    }
+
+    output.finish().wrap_err("removing stale output")
+}
+
+fn check(opts: Opts) -> Result<()> {
+    let ctx = Context::new(opts)?;
 
-    write::initialize(&ctx.opts.output).wrap_err("initializing output")?;
+    let posts = read_posts(&ctx.opts.input.join("posts"), true)
+        .wrap_err_with(|| format!("reading posts from {}", ctx.opts.input.display()))?;
+    let count = posts.len();
 
+    let mut errors = 0;
+    let mut valid_posts = vec![];
+    let mut bodies = vec![];
     for post in posts {
-        let dir = ctx.opts.output.join("blog").join("posts").join(&post.name);
-        std::fs::create_dir_all(&dir)?;
+        let result = post.and_then(|post| {
+            let body = render_body(
+                &ctx,
+                &post.relative_to,
+                &post.body_md,
+                &post.frontmatter.common.images,
+            )
+            .wrap_err_with(|| format!("rendering post {}", post.name))?;
+            render_post(&ctx, &post, &body)?;
+            Ok((post, body))
+        });
+        match result {
+            Ok((post, body)) => {
+                valid_posts.push(post);
+                bodies.push(body);
+            }
+            Err(err) => {
+                eprintln!("error: {err:?}");
+                errors += 1;
+            }
+        }
+    }
 
-        let html = render_post(&mut ctx, &post)?;
+    let pages = pages::read_pages(&ctx.opts.input.join("pages"), true)
+        .wrap_err_with(|| format!("reading pages from {}", ctx.opts.input.display()))?;
+    let page_count = pages.len();
+    let mut page_errors = 0;
+    for page in pages {
+        let result = page.and_then(|page| {
+            let body = pages::render_page_body(&ctx, &page)?;
+            pages::render_page(&ctx, &page, &body)
+        });
+        if let Err(err) = result {
+            eprintln!("error: {err:?}");
+            page_errors += 1;
+        }
+    }
 
-        std::fs::write(dir.join("index.html"), html)?;
+    let problems = links::check_links(&valid_posts, &bodies);
+    for problem in &problems {
+        eprintln!("error: {problem}");
     }
 
-    let static_dir = ctx.opts.output.join("static");
-    std::fs::create_dir(&static_dir).wrap_err("creating static")?;
-    for (name, content) in ctx.static_files {
-        std::fs::write(static_dir.join(name), content).wrap_err("writing static file")?;
+    ensure!(errors == 0, "{errors} of {count} posts are invalid");
+    ensure!(
+        page_errors == 0,
+        "{page_errors} of {page_count} pages are invalid"
+    );
+    ensure!(problems.is_empty(), "found {} broken links", problems.len());
+    println!("all {count} posts and {page_count} pages are valid");
+    Ok(())
+}
+
+fn clean(output: &Path) -> Result<()> {
+    match std::fs::remove_dir_all(output) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result.wrap_err_with(|| format!("removing {}", output.display())),
     }
+}
+
+fn new_post(input: &Path, slug: &str, dir: bool) -> Result<()> {
+    #[derive(askama::Template)]
+    #[template(path = "../templates/new-post.md")]
+    struct NewPostTemplate<'a> {
+        title: &'a str,
+        date: &'a str,
+    }
+
+    ensure!(
+        !slug.is_empty()
+            && slug
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-'),
+        "invalid slug `{slug}`, only lowercase letters, digits and `-` are allowed"
+    );
+
+    let posts = input.join("posts");
+    for existing in [posts.join(format!("{slug}.md")), posts.join(slug)] {
+        ensure!(!existing.exists(), "{} already exists", existing.display());
+    }
+
+    let mut title = slug.replace('-', " ");
+    title[..1].make_ascii_uppercase();
+    let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+
+    let content = NewPostTemplate {
+        title: &title,
+        date: &date,
+    }
+    .render()
+    .wrap_err("failed to render new post template")?;
+
+    let path = if dir {
+        posts.join(slug).join("index.md")
+    } else {
+        posts.join(format!("{slug}.md"))
+    };
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    std::fs::write(&path, content).wrap_err_with(|| format!("writing {}", path.display()))?;
 
+    println!("created {}", path.display());
     Ok(())
 }
 
-fn collect_posts(path: &Path) -> Result<Vec<Post>> {
+fn collect_posts(path: &Path, drafts: bool) -> Result<Vec<Post>> {
+    read_posts(path, drafts)?.into_iter().collect()
+}
+
+/// Reads all posts, keeping going after invalid posts so that all errors can be reported.
+fn read_posts(path: &Path, drafts: bool) -> Result<Vec<Result<Post>>> {
     let mut posts = vec![];
     let entries = std::fs::read_dir(path)?;
 
@@ -167,8 +779,13 @@ fn collect_posts(path: &Path) -> Result<Vec<Post>> {
         let name = entry.file_name();
         let name = name.to_str().ok_or_eyre("invalid UTF-8 filename")?;
 
-        let post =
-            collect_post(&entry, name).wrap_err_with(|| format!("generating post {name}"))?;
+        let post = collect_post(&entry, name).wrap_err_with(|| format!("generating post {name}"));
+        if let Ok(post) = &post
+            && post.frontmatter.common.draft
+            && !drafts
+        {
+            continue;
+        }
         posts.push(post);
     }
 
@@ -201,105 +818,705 @@ fn collect_post(entry: &DirEntry, name: &str) -> Result<Post> {
         )
     };
 
-    let rest = content
-        .strip_prefix("---\n")
-        .ok_or_eyre("post must start with `---`")?;
-    let (frontmatter, body) = rest
-        .split_once("---\n")
-        .ok_or_eyre("unterminated frontmatter, needs another `---`")?;
-
-    let frontmatter =
-        serde_norway::from_str::<Frontmatter>(frontmatter).wrap_err("¡nvalid frontmatter")?;
+    let (frontmatter, body, body_line) = parse_frontmatter::<Frontmatter>(&content)?;
+    let date = PostDate::parse(&frontmatter.date).wrap_err("invalid date in frontmatter")?;
+    let updated = frontmatter.common.updated()?;
+    for tag in &frontmatter.tags {
+        ensure!(
+            !slugify(tag).is_empty(),
+            "invalid tag `{tag}`, must contain letters or digits"
+        );
+    }
 
     Ok(Post {
         name,
         frontmatter,
+        date,
         body_md: body.to_owned(),
+        body_line,
         relative_to,
+        updated,
     })
 }
 
+/// Splits a markdown file into its frontmatter and body, also returning the line the body
+/// starts at.
+fn parse_frontmatter<T: serde::de::DeserializeOwned>(content: &str) -> Result<(T, &str, usize)> {
+    let rest = content
+        .strip_prefix("---\n")
+        .ok_or_eyre("file must start with `---`")?;
+    let (frontmatter, body) = rest
+        .split_once("---\n")
+        .ok_or_eyre("unterminated frontmatter, needs another `---`")?;
+
+    let frontmatter = serde_norway::from_str::<T>(frontmatter).wrap_err("invalid frontmatter")?;
+    let body_line = content[..content.len() - body.len()].matches('\n').count() + 1;
+
+    Ok((frontmatter, body, body_line))
+}
+
 #[derive(serde::Deserialize)]
 struct Frontmatter {
-    title: String,
+    #[serde(flatten)]
+    common: CommonFrontmatter,
     date: String,
+    #[serde(default)]
+    tags: Vec<String>,
 }
 
-fn render_post(ctx: &mut Context, post: &Post) -> Result<String> {
-    #[derive(askama::Template)]
-    #[template(path = "../templates/post.html")]
+/// The frontmatter fields of both posts and pages.
+#[derive(Default, serde::Deserialize)]
+struct CommonFrontmatter {
+    title: String,
+    /// When the content was last changed significantly, for the sitemap.
+    #[serde(default)]
+    updated: Option<String>,
+    #[serde(default)]
+    draft: bool,
+    /// Whether to render a table of contents from the headings.
+    #[serde(default)]
+    toc: bool,
+    /// Encoding settings for images, keyed by the path used in the markdown.
+    #[serde(default)]
+    images: BTreeMap<String, ImageOverrides>,
+}
+
+impl CommonFrontmatter {
+    fn updated(&self) -> Result<Option<PostDate>> {
+        self.updated
+            .as_deref()
+            .map(PostDate::parse)
+            .transpose()
+            .wrap_err("invalid updated date in frontmatter")
+    }
+
+    /// The table of contents for the template, empty if it is disabled.
+    fn toc(&self, body: &RenderedBody) -> String {
+        if self.toc {
+            render_toc(&body.headings)
+        } else {
+            String::new()
+        }
+    }
+}
+
+fn render_post(ctx: &Context, post: &Post, body: &RenderedBody) -> Result<String> {
+    #[derive(serde::Serialize)]
     struct PostTemplate<'a> {
         title: &'a str,
+        date: DateContext,
+        tags: Vec<tags::TagLink<'a>>,
+        draft: bool,
+        toc: String,
         body: &'a str,
-        theme_css_path: &'a str,
+        site: SiteContext<'a>,
+        stylesheets: &'a [String],
     }
 
-    let body = render_body(ctx, &post.relative_to, &post.body_md)?;
+    let template = PostTemplate {
+        title: &post.frontmatter.common.title,
+        date: DateContext::new(&post.date),
+        tags: tags::tag_links(post),
+        draft: post.frontmatter.common.draft,
+        toc: post.frontmatter.common.toc(body),
+        body: &body.html,
+        site: SiteContext::new(&ctx.site),
+        stylesheets: &ctx.stylesheets,
+    };
+    ctx.templates.render("post.html", template)
+}
 
-    PostTemplate {
-        title: &post.frontmatter.title,
-        body: &body,
-        theme_css_path: &ctx.theme_css_path,
+fn render_index(ctx: &Context, posts: &[Post]) -> Result<String> {
+    #[derive(serde::Serialize)]
+    struct IndexTemplate<'a> {
+        title: &'a str,
+        posts: Vec<PostSummary<'a>>,
+        site: SiteContext<'a>,
+        stylesheets: &'a [String],
     }
-    .render()
-    .wrap_err("failed to render template")
+
+    let template = IndexTemplate {
+        title: &ctx.site.title,
+        posts: posts.iter().map(PostSummary::new).collect(),
+        site: SiteContext::new(&ctx.site),
+        stylesheets: &ctx.stylesheets,
+    };
+    ctx.templates.render("index.html", template)
+}
+
+/// Renders math to MathML, leaving other events untouched.
+fn render_math(ev: Event<'_>) -> Result<Event<'_>> {
+    let (latex, display) = match &ev {
+        Event::InlineMath(latex) => (latex, latex2mathml::DisplayStyle::Inline),
+        Event::DisplayMath(latex) => (latex, latex2mathml::DisplayStyle::Block),
+        _ => return Ok(ev),
+    };
+
+    let mathml = latex2mathml::latex_to_mathml(latex, display)
+        .map_err(|err| eyre!("invalid math `{latex}`: {err}"))?;
+    Ok(Event::InlineHtml(mathml.into()))
+}
+
+/// Points links to local files at their copies in the static files, leaving other events
+/// untouched.
+fn rewrite_link<'a>(ctx: &Context, relative_to: &Path, ev: Event<'a>) -> Result<Event<'a>> {
+    let Event::Start(Tag::Link {
+        link_type,
+        dest_url,
+        title,
+        id,
+    }) = ev
+    else {
+        return Ok(ev);
+    };
+
+    let dest_url = match ctx.add_linked_file(relative_to, &dest_url)? {
+        Some(url) => url.into(),
+        None => dest_url,
+    };
+    Ok(Event::Start(Tag::Link {
+        link_type,
+        dest_url,
+        title,
+        id,
+    }))
+}
+
+/// Renders markdown without wrapping it in a paragraph, for captions.
+fn render_inline(ctx: &Context, relative_to: &Path, md: &str) -> Result<String> {
+    let events = pulldown_cmark::Parser::new_ext(md, markdown_options())
+        .filter(|ev| {
+            !matches!(
+                ev,
+                Event::Start(Tag::Paragraph) | Event::End(TagEnd::Paragraph)
+            )
+        })
+        .map(|ev| render_math(rewrite_link(ctx, relative_to, ev)?))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events.into_iter());
+    Ok(html)
+}
+
+/// Renders the headings as nested lists of links to them.
+fn render_toc(headings: &[Heading]) -> String {
+    let mut html = String::new();
+    // The levels of the currently open lists.
+    let mut levels = Vec::<usize>::new();
+
+    for heading in headings {
+        while levels.last().is_some_and(|&level| level > heading.level) {
+            html.push_str("</li></ul>");
+            levels.pop();
+        }
+        if levels.last() == Some(&heading.level) {
+            html.push_str("</li>");
+        } else {
+            html.push_str("<ul>");
+            levels.push(heading.level);
+        }
+        html.push_str(&format!(
+            r##"<li><a href="#{}">{}</a>"##,
+            heading.id,
+            escape_html(&heading.title)
+        ));
+    }
+    for _ in levels {
+        html.push_str("</li></ul>");
+    }
+
+    html
 }
 
-fn render_body(ctx: &mut Context, relative_to: &Path, md: &str) -> Result<String> {
+/// Creates an id for a heading from its title that none of the previous headings use.
+fn heading_id(title: &str, previous: &[Heading]) -> String {
+    let slug = slugify(title);
+    let slug = if slug.is_empty() {
+        "section".to_owned()
+    } else {
+        slug
+    };
+    let mut id = slug.clone();
+    let mut n = 1;
+    while previous.iter().any(|heading| heading.id == id) {
+        id = format!("{slug}-{n}");
+        n += 1;
+    }
+    id
+}
+
+struct RenderedBody {
+    html: String,
+    headings: Vec<Heading>,
+}
+
+struct Heading {
+    level: usize,
+    id: String,
+    title: String,
+}
+
+fn markdown_options() -> Options {
     let mut options = pulldown_cmark::Options::empty();
-    options |= Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES | Options::ENABLE_STRIKETHROUGH;
-    let mut parser = pulldown_cmark::Parser::new_ext(md, options);
+    options |= Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_MATH;
+    options
+}
+
+/// An image in the markdown, `![alt](dest_url "title")`.
+struct MarkdownImage<'a> {
+    dest_url: &'a str,
+    title: &'a str,
+    alt: String,
+}
+
+impl<'a> MarkdownImage<'a> {
+    /// Consumes the events of the alt text up to the end of the image.
+    fn read<'e>(
+        dest_url: &'a str,
+        title: &'a str,
+        parser: &mut impl Iterator<Item = Event<'e>>,
+    ) -> Result<Self> {
+        let mut alt = String::new();
+        loop {
+            match parser.next() {
+                Some(Event::End(TagEnd::Image)) => break,
+                Some(Event::Text(text) | Event::Code(text)) => alt.push_str(&text),
+                Some(_) => {}
+                None => bail!("No end tag for image"),
+            }
+        }
+        Ok(Self {
+            dest_url,
+            title,
+            alt,
+        })
+    }
+}
+
+/// Renders an image as a `<picture>` with all generated formats and sizes. If it is `alone`
+/// in its paragraph and has a caption in its title, it is wrapped in a `<figure>`, which is
+/// returned as well so that the caller can drop the paragraph.
+fn render_image(
+    ctx: &Context,
+    relative_to: &Path,
+    image_overrides: &BTreeMap<String, ImageOverrides>,
+    image: &MarkdownImage<'_>,
+    alone: bool,
+    lazy: bool,
+) -> Result<(String, bool)> {
+    let MarkdownImage {
+        dest_url,
+        title,
+        alt,
+    } = image;
+    let (caption, overrides) = ImageOverrides::from_title(title)?;
+    let figure = alone && !caption.is_empty();
+    let overrides = match image_overrides.get(*dest_url) {
+        Some(frontmatter) => overrides.or(frontmatter),
+        None => overrides,
+    };
+    let sources = ctx
+        .add_image(&relative_to.join(dest_url), &overrides)
+        .wrap_err_with(|| format!("adding image {dest_url}"))?;
+
+    let mut html = String::new();
+    if figure {
+        html.push_str("<figure>");
+    }
+    html.push_str("<picture>");
+    let sizes = &ctx.site.images.sizes;
+    let srcset = |source: &PictureSource| {
+        if source.variants.len() > 1 {
+            format!(r#" srcset="{}" sizes="{sizes}""#, source.srcset())
+        } else {
+            String::new()
+        }
+    };
+    // The first image is likely visible right away, don't delay it.
+    let loading = if lazy {
+        r#" loading="lazy" decoding="async""#
+    } else {
+        ""
+    };
+    let placeholder = sources
+        .placeholder
+        .as_ref()
+        .map(|url| format!(r#" class="image-placeholder" style="background-image: url('{url}')""#))
+        .unwrap_or_default();
+    let dimensions = sources
+        .dimensions
+        .map(|(width, height)| format!(r#" height="{height}" width="{width}""#))
+        .unwrap_or_default();
+    // Images inside text keep their caption as a tooltip.
+    let tooltip = if figure || caption.is_empty() {
+        String::new()
+    } else {
+        format!(r#" title="{}""#, escape_html(caption))
+    };
+
+    for source in &sources.sources {
+        html.push_str(&format!(
+            r#"<source srcset="{}" sizes="{sizes}" type="{}">"#,
+            source.srcset(),
+            source.media_type
+        ));
+    }
+    html.push_str(&format!(
+        r#"<img src="{}"{} alt="{}"{tooltip}{dimensions}{loading}{placeholder}>"#,
+        sources.fallback.largest().path,
+        srcset(&sources.fallback),
+        escape_html(alt),
+    ));
+    html.push_str("</picture>");
+    if figure {
+        html.push_str(&format!(
+            "<figcaption>{}</figcaption></figure>",
+            render_inline(ctx, relative_to, caption)?
+        ));
+    }
+    Ok((html, figure))
+}
+
+fn render_body(
+    ctx: &Context,
+    relative_to: &Path,
+    md: &str,
+    image_overrides: &BTreeMap<String, ImageOverrides>,
+) -> Result<RenderedBody> {
+    let mut parser = pulldown_cmark::Parser::new_ext(md, markdown_options()).peekable();
 
     let mut events = vec![];
+    let mut headings = Vec::<Heading>::new();
+    let mut image_count = 0;
 
     while let Some(ev) = parser.next() {
-        dbg!(&ev);
         match ev {
+            Event::Start(Tag::Heading {
+                level,
+                id,
+                classes,
+                attrs,
+            }) => {
+                let mut inner = vec![];
+                let mut title = String::new();
+                loop {
+                    match parser.next() {
+                        Some(Event::End(TagEnd::Heading(_))) => break,
+                        Some(Event::Start(Tag::Image {
+                            link_type: _,
+                            dest_url,
+                            title: image_title,
+                            id: _,
+                        })) => {
+                            let image = MarkdownImage::read(&dest_url, &image_title, &mut parser)?;
+                            let (html, _) = render_image(
+                                ctx,
+                                relative_to,
+                                image_overrides,
+                                &image,
+                                false,
+                                image_count > 0,
+                            )?;
+                            image_count += 1;
+                            inner.push(Event::InlineHtml(html.into()));
+                        }
+                        Some(ev) => {
+                            if let Event::Text(text) | Event::Code(text) = &ev {
+                                title.push_str(text);
+                            }
+                            inner.push(render_math(rewrite_link(ctx, relative_to, ev)?)?);
+                        }
+                        None => bail!("No end tag for heading"),
+                    }
+                }
+
+                let id = match id {
+                    Some(id) => id.to_string(),
+                    None => heading_id(&title, &headings),
+                };
+
+                events.push(Event::Start(Tag::Heading {
+                    level,
+                    id: Some(id.clone().into()),
+                    classes,
+                    attrs,
+                }));
+                events.extend(inner);
+                events.extend([
+                    Event::InlineHtml(
+                        format!(
+                            r##"<a class="heading-anchor" href="#{id}" aria-label="Link to this section">#</a>"##
+                        )
+                        .into(),
+                    ),
+                    Event::End(TagEnd::Heading(level)),
+                ]);
+
+                headings.push(Heading {
+                    level: level as usize,
+                    id,
+                    title,
+                });
+            }
             Event::Start(Tag::Image {
                 link_type: _,
                 dest_url,
-                title: _,
+                title,
                 id: _,
             }) => {
-                let Some(Event::Text(alt)) = parser.next() else {
-                    bail!("No alt text for image tag");
-                };
-                let Some(Event::End(TagEnd::Image)) = parser.next() else {
-                    bail!("No end tag for image");
-                };
+                let image = MarkdownImage::read(&dest_url, &title, &mut parser)?;
+
+                // A figure can't be inside a paragraph, so only images that are alone in their
+                // paragraph get one, replacing the paragraph.
+                let alone = matches!(events.last(), Some(Event::Start(Tag::Paragraph)))
+                    && matches!(parser.peek(), Some(Event::End(TagEnd::Paragraph)));
 
-                let sources = ctx.add_image(&relative_to.join(dest_url.as_ref()))?;
+                let (html, figure) = render_image(
+                    ctx,
+                    relative_to,
+                    image_overrides,
+                    &image,
+                    alone,
+                    image_count > 0,
+                )?;
+                image_count += 1;
+                if figure {
+                    events.pop();
+                    parser.next();
+                }
 
                 events.extend([
                     Event::Start(Tag::HtmlBlock),
-                    Event::Html("<picture>".into()),
+                    Event::Html(html.into()),
+                    Event::End(TagEnd::HtmlBlock),
                 ]);
-                for source in sources.sources {
-                    events.push(Event::Html(
-                        format!(
-                            r#"<source srcset="{}" type="{}">"#,
-                            source.path, source.media_type
-                        )
-                        .into(),
-                    ));
+            }
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                let mut code = String::new();
+                loop {
+                    match parser.next() {
+                        Some(Event::Text(text)) => code.push_str(&text),
+                        Some(Event::End(TagEnd::CodeBlock)) => break,
+                        _ => bail!("unexpected content in code block"),
+                    }
                 }
-                events.extend([
-                    Event::Html(
-                        format!(
-                            r#"<img src="{}" alt="{}" height="{}" width="{}">"#,
-                            sources.fallback_path, alt, sources.height, sources.width
-                        )
-                        .into(),
+
+                // The info string may contain more than the language, like `rust,ignore`.
+                let lang = info.split([' ', ',']).next().unwrap_or_default().to_owned();
+
+                let html = match highlight::highlight(&lang, &code)? {
+                    Some(highlighted) => format!(
+                        r#"<pre class="{}"><code class="language-{}">{highlighted}</code></pre>"#,
+                        highlight::CODE_CLASS,
+                        escape_html(&lang),
                     ),
-                    Event::Html("</picture>".into()),
+                    None => format!("<pre><code>{}</code></pre>", escape_html(&code)),
+                };
+
+                events.extend([
+                    Event::Start(Tag::HtmlBlock),
+                    Event::Html(html.into()),
                     Event::End(TagEnd::HtmlBlock),
                 ]);
             }
-            ev => events.push(ev),
+            ev => events.push(render_math(rewrite_link(ctx, relative_to, ev)?)?),
+        }
+    }
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events.into_iter());
+    Ok(RenderedBody { html, headings })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Context, Heading, Opts, heading_id, render_body, render_toc, slugify};
+    use std::{
+        collections::BTreeMap,
+        path::{Path, PathBuf},
+    };
+
+    /// A directory in the system temp directory that is removed again when dropped.
+    pub(crate) struct TempDir(PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("blogamer-test-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        pub(crate) fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
         }
     }
 
-    let mut body = String::new();
-    pulldown_cmark::html::push_html(&mut body, events.into_iter());
-    Ok(body)
+    fn context(input: &Path) -> Context {
+        Context::new(Opts {
+            optimize: false,
+            formats: None,
+            jpeg_quality: None,
+            avif_quality: None,
+            avif_speed: None,
+            input: input.to_owned(),
+            base_url: None,
+            cache_dir: Some(input.join("cache")),
+        })
+        .unwrap()
+    }
+
+    fn heading(level: usize, title: &str) -> Heading {
+        Heading {
+            level,
+            id: slugify(title),
+            title: title.to_owned(),
+        }
+    }
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  `code` & more  "), "code-more");
+        assert_eq!(slugify("Ünïcode Ok"), "ünïcode-ok");
+        assert_eq!(slugify("?!"), "");
+    }
+
+    #[test]
+    fn duplicate_heading_ids() {
+        let mut headings = vec![];
+        for title in ["Intro", "Intro 1", "Intro", "Intro", "", "?"] {
+            let id = heading_id(title, &headings);
+            headings.push(Heading {
+                level: 2,
+                id,
+                title: title.to_owned(),
+            });
+        }
+        let ids = headings.iter().map(|h| h.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            [
+                "intro",
+                "intro-1",
+                "intro-2",
+                "intro-3",
+                "section",
+                "section-1"
+            ]
+        );
+    }
+
+    #[test]
+    fn toc_nesting() {
+        let toc = render_toc(&[heading(2, "A"), heading(3, "B"), heading(2, "C")]);
+        assert_eq!(
+            toc,
+            r##"<ul><li><a href="#a">A</a><ul><li><a href="#b">B</a></li></ul></li><li><a href="#c">C</a></li></ul>"##
+        );
+    }
+
+    #[test]
+    fn toc_skipped_levels() {
+        let toc = render_toc(&[
+            heading(2, "A"),
+            heading(4, "B"),
+            heading(3, "C"),
+            heading(2, "D"),
+        ]);
+        assert_eq!(
+            toc,
+            r##"<ul><li><a href="#a">A</a><ul><li><a href="#b">B</a></li></ul><ul><li><a href="#c">C</a></li></ul></li><li><a href="#d">D</a></li></ul>"##
+        );
+    }
+
+    #[test]
+    fn toc_starting_deeper() {
+        let toc = render_toc(&[heading(3, "A"), heading(2, "B")]);
+        assert_eq!(
+            toc,
+            r##"<ul><li><a href="#a">A</a></li></ul><ul><li><a href="#b">B</a></li></ul>"##
+        );
+    }
+
+    #[test]
+    fn toc_escapes_titles() {
+        let toc = render_toc(&[heading(2, "<a> & b")]);
+        assert_eq!(
+            toc,
+            r##"<ul><li><a href="#a-b">&lt;a&gt; &amp; b</a></li></ul>"##
+        );
+        assert_eq!(render_toc(&[]), "");
+    }
+
+    #[test]
+    fn image_in_heading() {
+        let dir = TempDir::new("image-in-heading");
+        std::fs::write(
+            dir.path().join("cat.svg"),
+            r#"<svg xmlns="http://www.w3.org/2000/svg"></svg>"#,
+        )
+        .unwrap();
+        let ctx = context(dir.path());
+
+        let body = render_body(
+            &ctx,
+            dir.path(),
+            "## A ![cute](cat.svg) cat\n",
+            &BTreeMap::new(),
+        )
+        .unwrap();
+
+        // The alt text is not part of the title.
+        assert_eq!(body.headings[0].id, "a-cat");
+        assert!(
+            body.html
+                .starts_with(r#"<h2 id="a-cat">A <picture><img src="/static/cat-"#),
+            "{}",
+            body.html
+        );
+        let static_files = ctx.static_files.lock().unwrap();
+        assert!(
+            static_files
+                .keys()
+                .any(|name| name.starts_with("cat-") && name.ends_with(".svg"))
+        );
+    }
+
+    #[test]
+    fn image_attributes_are_escaped() {
+        let dir = TempDir::new("image-attributes");
+        std::fs::write(
+            dir.path().join("cat.svg"),
+            r#"<svg xmlns="http://www.w3.org/2000/svg"></svg>"#,
+        )
+        .unwrap();
+        let ctx = context(dir.path());
+
+        let body = render_body(
+            &ctx,
+            dir.path(),
+            r#"Look: ![a "quoted" cat](cat.svg "<b>bold</b>")"#,
+            &BTreeMap::new(),
+        )
+        .unwrap();
+
+        assert!(
+            body.html
+                .contains(r#" alt="a &quot;quoted&quot; cat" title="&lt;b&gt;bold&lt;/b&gt;">"#),
+            "{}",
+            body.html
+        );
+    }
 }
@@ -0,0 +1,310 @@
+//! Checking links between posts and to local files.
+
+use crate::{Post, RenderedBody, markdown_options};
+use pulldown_cmark::{Event, Tag};
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+pub(crate) struct LinkProblem {
+    post: String,
+    line: usize,
+    message: String,
+}
+
+impl fmt::Display for LinkProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.post, self.line, self.message)
+    }
+}
+
+/// Checks all links in the posts, returning every broken one.
+///
+/// Links to other posts are resolved as URLs relative to the post's page, links to
+/// other local files relative to the post's markdown file. External links are not checked.
+pub(crate) fn check_links(posts: &[Post], bodies: &[RenderedBody]) -> Vec<LinkProblem> {
+    let anchors = posts
+        .iter()
+        .zip(bodies)
+        .map(|(post, body)| {
+            let ids = body.headings.iter().map(|heading| heading.id.as_str());
+            (post.name.as_str(), ids.collect::<Vec<_>>())
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut problems = vec![];
+
+    for post in posts {
+        let parser = pulldown_cmark::Parser::new_ext(&post.body_md, markdown_options());
+        for (ev, range) in parser.into_offset_iter() {
+            let Event::Start(Tag::Link { dest_url, .. }) = ev else {
+                continue;
+            };
+
+            if let Err(message) = check_link(post, &dest_url, &anchors) {
+                problems.push(LinkProblem {
+                    post: post.name.clone(),
+                    line: post.body_line + post.body_md[..range.start].matches('\n').count(),
+                    message,
+                });
+            }
+        }
+    }
+
+    problems
+}
+
+fn check_link(post: &Post, dest: &str, anchors: &HashMap<&str, Vec<&str>>) -> Result<(), String> {
+    if is_external(dest) {
+        // Browsers read `name:file.pdf` as a URL with the scheme `name`, not as a file.
+        if post.relative_to.join(dest).exists() {
+            return Err(format!(
+                "link to `{dest}` is a URL with a scheme, use `./{dest}` to link to the file"
+            ));
+        }
+        return Ok(());
+    }
+
+    let (path, fragment) = match dest.split_once('#') {
+        Some((path, fragment)) => (path, Some(fragment)),
+        None => (dest, None),
+    };
+    let path = path.split('?').next().unwrap_or_default();
+
+    let target = if path.is_empty() {
+        post.name.clone()
+    } else if linked_file(&post.relative_to, dest).is_some() {
+        return Ok(());
+    } else if path.ends_with(".md") && !path.starts_with('/') {
+        return Err(format!(
+            "link to `{dest}` points to a markdown file, link to the post's URL instead"
+        ));
+    } else {
+        let url = resolve_url(&format!("/blog/posts/{}/", post.name), path);
+        match url
+            .strip_prefix("/blog/posts/")
+            .map(|rest| rest.trim_end_matches('/'))
+        {
+            Some(name) if !name.is_empty() && !name.contains('/') => name.to_owned(),
+            // Other pages of the site are not known here.
+            _ if path.starts_with('/') || !url.starts_with("/blog/posts/") => return Ok(()),
+            _ => return Err(format!("link to `{dest}` does not point to a file or post")),
+        }
+    };
+
+    let Some(target_anchors) = anchors.get(target.as_str()) else {
+        return Err(format!(
+            "link to `{dest}` points to unknown post `{target}`"
+        ));
+    };
+    if let Some(fragment) = fragment
+        && !fragment.is_empty()
+        && !target_anchors.contains(&fragment)
+    {
+        return Err(format!(
+            "link to `{dest}` points to unknown heading `#{fragment}` in post `{target}`"
+        ));
+    }
+
+    Ok(())
+}
+
+/// The local file a link points to if it is copied into the output, together with the
+/// query and fragment of the link. Markdown files and directories are not copied, links
+/// to posts have to use their URL.
+pub(crate) fn linked_file<'a>(relative_to: &Path, dest: &'a str) -> Option<(PathBuf, &'a str)> {
+    if is_external(dest) || dest.starts_with('/') {
+        return None;
+    }
+
+    let split = dest.find(['?', '#']).unwrap_or(dest.len());
+    let (path, suffix) = dest.split_at(split);
+    if path.is_empty() {
+        return None;
+    }
+
+    let path = relative_to.join(path);
+    if !path.is_file() || path.extension().is_some_and(|ext| ext == "md") {
+        return None;
+    }
+    Some((path, suffix))
+}
+
+/// Whether the link starts with a URL scheme like `https:` or is protocol-relative.
+pub(crate) fn is_external(dest: &str) -> bool {
+    if dest.starts_with("//") {
+        return true;
+    }
+    let Some((scheme, _)) = dest.split_once(':') else {
+        return false;
+    };
+    let mut chars = scheme.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+/// Resolves a relative URL path against the path of a page.
+pub(crate) fn resolve_url(base: &str, path: &str) -> String {
+    let mut segments = if path.starts_with('/') {
+        vec![]
+    } else {
+        base.split('/')
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+    };
+
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    let mut url = format!("/{}", segments.join("/"));
+    if path.ends_with('/') && url != "/" {
+        url.push('/');
+    }
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_link, resolve_url};
+    use crate::{CommonFrontmatter, Frontmatter, Post, PostDate, tests::TempDir};
+    use std::{collections::HashMap, path::Path};
+
+    /// Creates a posts directory with a directory post `dir-post` and a file post `file-post`.
+    fn posts_dir(test: &str) -> TempDir {
+        let dir = TempDir::new(&format!("links-{test}"));
+        let path = dir.path();
+        std::fs::create_dir_all(path.join("dir-post/sub")).unwrap();
+        std::fs::write(path.join("dir-post/index.md"), "").unwrap();
+        std::fs::write(path.join("dir-post/image.png"), "").unwrap();
+        std::fs::write(path.join("file-post.md"), "").unwrap();
+        dir
+    }
+
+    fn post(name: &str, relative_to: &Path) -> Post {
+        Post {
+            name: name.to_owned(),
+            relative_to: relative_to.to_owned(),
+            frontmatter: Frontmatter {
+                common: CommonFrontmatter {
+                    title: name.to_owned(),
+                    ..Default::default()
+                },
+                date: "2025-06-10".to_owned(),
+                tags: vec![],
+            },
+            date: PostDate::parse("2025-06-10").unwrap(),
+            body_md: String::new(),
+            body_line: 1,
+            updated: None,
+        }
+    }
+
+    fn anchors() -> HashMap<&'static str, Vec<&'static str>> {
+        HashMap::from([
+            ("dir-post", vec!["intro"]),
+            ("file-post", vec!["intro", "details"]),
+        ])
+    }
+
+    #[test]
+    fn external_links() {
+        let dir = posts_dir("external");
+        let post = post("file-post", dir.path());
+        for dest in [
+            "https://example.com/missing",
+            "mailto:me@example.com",
+            "//example.com/x",
+            "git+ssh://example.com/repo",
+            "missing:file.pdf",
+        ] {
+            assert_eq!(check_link(&post, dest, &anchors()), Ok(()), "{dest}");
+        }
+        // Colons elsewhere don't make a scheme.
+        for dest in [
+            "#sec:intro",
+            "missing.txt?v=a:b",
+            "./missing:file.pdf",
+            "1a:b",
+        ] {
+            assert!(check_link(&post, dest, &anchors()).is_err(), "{dest}");
+        }
+    }
+
+    #[test]
+    fn links_to_posts() {
+        let dir = posts_dir("posts");
+        let post = post("dir-post", &dir.path().join("dir-post"));
+        for dest in [
+            "#intro",
+            "../file-post/",
+            "../file-post",
+            "../file-post/#details",
+            "/blog/posts/file-post/",
+            "/blog/posts/file-post/#intro",
+            // Other pages of the site are not checked.
+            "/about/",
+            "/blog/",
+        ] {
+            assert_eq!(check_link(&post, dest, &anchors()), Ok(()), "{dest}");
+        }
+        for dest in [
+            "#missing",
+            "../missing/",
+            "../file-post/#missing",
+            "/blog/posts/missing/",
+            "other/",
+        ] {
+            assert!(check_link(&post, dest, &anchors()).is_err(), "{dest}");
+        }
+    }
+
+    #[test]
+    fn links_to_local_files() {
+        let dir = posts_dir("files");
+        let post = post("dir-post", &dir.path().join("dir-post"));
+        for dest in [
+            "image.png",
+            "./image.png",
+            "image.png?v=1#top",
+            "image.png?v=a:b",
+        ] {
+            assert_eq!(check_link(&post, dest, &anchors()), Ok(()), "{dest}");
+        }
+        // Neither copied to the output nor a post URL.
+        for dest in ["missing.png", "index.md", "../file-post.md", "sub", "sub/"] {
+            assert!(check_link(&post, dest, &anchors()).is_err(), "{dest}");
+        }
+    }
+
+    #[test]
+    fn links_from_file_posts() {
+        let dir = posts_dir("file-posts");
+        let post = post("file-post", dir.path());
+        for dest in ["dir-post/image.png", "../dir-post/", "#details"] {
+            assert_eq!(check_link(&post, dest, &anchors()), Ok(()), "{dest}");
+        }
+        for dest in ["dir-post.md", "dir-post", "dir-post/"] {
+            assert!(check_link(&post, dest, &anchors()).is_err(), "{dest}");
+        }
+    }
+
+    #[test]
+    fn resolve() {
+        let base = "/blog/posts/a/";
+        assert_eq!(resolve_url(base, "../b/"), "/blog/posts/b/");
+        assert_eq!(resolve_url(base, "../b"), "/blog/posts/b");
+        assert_eq!(resolve_url(base, "./c.png"), "/blog/posts/a/c.png");
+        assert_eq!(resolve_url(base, "/about/"), "/about/");
+        assert_eq!(resolve_url(base, "../../../../"), "/");
+        assert_eq!(resolve_url(base, "x//y/"), "/blog/posts/a/x/y/");
+    }
+}
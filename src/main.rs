@@ -1,6 +1,6 @@
 use clap::Parser;
 
 fn main() -> color_eyre::Result<()> {
-    let opts = blogamer::Opts::parse();
-    blogamer::generate(opts)
+    let cli = blogamer::Cli::parse();
+    blogamer::run(cli)
 }
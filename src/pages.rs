@@ -0,0 +1,140 @@
+//! Standalone pages outside the blog, like `/about/`.
+//!
+//! Every markdown file in `pages/` in the input directory becomes a page at the same path,
+//! `pages/about.md` and `pages/about/index.md` are both rendered to `/about/`. A
+//! `pages/index.md` replaces the blog index at the root of the site.
+
+use crate::{
+    CommonFrontmatter, Context, PostDate, RenderedBody, parse_frontmatter, render_body,
+    templates::SiteContext,
+};
+use color_eyre::{
+    Result,
+    eyre::{OptionExt, WrapErr},
+};
+use std::path::{Path, PathBuf};
+
+pub(crate) struct Page {
+    /// The path of the page in the URL without surrounding slashes, empty for the root.
+    pub(crate) path: String,
+    pub(crate) frontmatter: CommonFrontmatter,
+    pub(crate) body_md: String,
+    pub(crate) updated: Option<PostDate>,
+    /// The directory that relative paths in the page are resolved against.
+    pub(crate) relative_to: PathBuf,
+}
+
+impl Page {
+    /// The absolute path of the page in the URL, like `/about/`.
+    pub(crate) fn url(&self) -> String {
+        if self.path.is_empty() {
+            "/".to_owned()
+        } else {
+            format!("/{}/", self.path)
+        }
+    }
+
+    /// The path of the rendered page in the output directory.
+    pub(crate) fn output_path(&self) -> PathBuf {
+        Path::new(&self.path).join("index.html")
+    }
+}
+
+pub(crate) fn collect_pages(dir: &Path, drafts: bool) -> Result<Vec<Page>> {
+    read_pages(dir, drafts)?.into_iter().collect()
+}
+
+/// Like `read_posts`, but also reads subdirectories and returns no pages if there is no
+/// `pages/` directory.
+pub(crate) fn read_pages(dir: &Path, drafts: bool) -> Result<Vec<Result<Page>>> {
+    let mut pages = vec![];
+    if dir.is_dir() {
+        read_pages_in(dir, "", drafts, &mut pages)?;
+    }
+    Ok(pages)
+}
+
+fn read_pages_in(
+    dir: &Path,
+    prefix: &str,
+    drafts: bool,
+    pages: &mut Vec<Result<Page>>,
+) -> Result<()> {
+    let entries = std::fs::read_dir(dir).wrap_err_with(|| format!("reading {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_str().ok_or_eyre("invalid UTF-8 filename")?;
+
+        if entry.file_type()?.is_dir() {
+            read_pages_in(&entry.path(), &format!("{prefix}{name}/"), drafts, pages)?;
+            continue;
+        }
+        // Other files are images and downloads used by the pages.
+        let Some(stem) = name.strip_suffix(".md") else {
+            continue;
+        };
+
+        let path = if stem == "index" {
+            prefix.trim_end_matches('/').to_owned()
+        } else {
+            format!("{prefix}{stem}")
+        };
+        let page = read_page(&entry.path(), path, dir)
+            .wrap_err_with(|| format!("generating page {}", entry.path().display()));
+        if let Ok(page) = &page
+            && page.frontmatter.draft
+            && !drafts
+        {
+            continue;
+        }
+        pages.push(page);
+    }
+    Ok(())
+}
+
+fn read_page(file: &Path, path: String, relative_to: &Path) -> Result<Page> {
+    let content = std::fs::read_to_string(file).wrap_err("reading contents")?;
+    let (frontmatter, body, _) = parse_frontmatter::<CommonFrontmatter>(&content)?;
+    let updated = frontmatter.updated()?;
+
+    Ok(Page {
+        path,
+        frontmatter,
+        body_md: body.to_owned(),
+        updated,
+        relative_to: relative_to.to_owned(),
+    })
+}
+
+pub(crate) fn render_page_body(ctx: &Context, page: &Page) -> Result<RenderedBody> {
+    render_body(
+        ctx,
+        &page.relative_to,
+        &page.body_md,
+        &page.frontmatter.images,
+    )
+    .wrap_err_with(|| format!("rendering page /{}", page.path))
+}
+
+pub(crate) fn render_page(ctx: &Context, page: &Page, body: &RenderedBody) -> Result<String> {
+    #[derive(serde::Serialize)]
+    struct PageTemplate<'a> {
+        title: &'a str,
+        draft: bool,
+        toc: String,
+        body: &'a str,
+        site: SiteContext<'a>,
+        stylesheets: &'a [String],
+    }
+
+    let template = PageTemplate {
+        title: &page.frontmatter.title,
+        draft: page.frontmatter.draft,
+        toc: page.frontmatter.toc(body),
+        body: &body.html,
+        site: SiteContext::new(&ctx.site),
+        stylesheets: &ctx.stylesheets,
+    };
+    ctx.templates.render("page.html", template)
+}
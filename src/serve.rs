@@ -0,0 +1,208 @@
+//! A local development server that rebuilds the site whenever the input changes.
+//!
+//! Pages are rebuilt into a temporary directory and served from there. Every HTML
+//! page gets a small script injected that polls the current build version and
+//! reloads the page once a newer build is available.
+
+use crate::Opts;
+use color_eyre::{
+    Result,
+    eyre::{WrapErr, eyre},
+};
+use notify::Watcher;
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+    },
+    time::Duration,
+};
+
+const VERSION_PATH: &str = "/__blogamer/version";
+
+pub(crate) fn serve(mut opts: Opts, drafts: bool, port: u16) -> Result<()> {
+    // Reused by later sessions for the same site, so that they don't leave full builds behind.
+    // Only one session can listen on the port at a time.
+    let input = std::fs::canonicalize(&opts.input).unwrap_or_else(|_| opts.input.clone());
+    let input_hash = crate::create_hash_string(input.as_os_str().as_encoded_bytes());
+    let output = std::env::temp_dir().join(format!("blogamer-serve-{input_hash}-{port}"));
+    if opts.base_url.is_none() {
+        opts.base_url = Some(format!("http://127.0.0.1:{port}"));
+    }
+
+    // Listen before building, another session might be using the output directory.
+    let server = tiny_http::Server::http(("127.0.0.1", port))
+        .map_err(|err| eyre!("failed to listen on port {port}: {err}"))?;
+
+    let version = Arc::new(AtomicU64::new(0));
+    rebuild(&opts, drafts, &output, &version);
+
+    {
+        let output = output.clone();
+        let version = version.clone();
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                if let Err(err) = respond(&output, &version, request) {
+                    eprintln!("error: {err:?}");
+                }
+            }
+        });
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).wrap_err("creating file watcher")?;
+    watcher
+        .watch(&opts.input, notify::RecursiveMode::Recursive)
+        .wrap_err_with(|| format!("watching {}", opts.input.display()))?;
+
+    println!("serving at http://127.0.0.1:{port}/");
+
+    // The cache may be inside the input directory, don't rebuild when it's written to.
+    let cache_dir = opts.cache_dir();
+
+    for event in &rx {
+        match event {
+            Ok(event) if is_relevant(&event, &cache_dir) => {}
+            Ok(_) => continue,
+            Err(err) => {
+                eprintln!("error: watching files: {err}");
+                continue;
+            }
+        }
+
+        // Editors tend to touch several files when saving, wait for them to settle.
+        while rx.recv_timeout(Duration::from_millis(100)).is_ok() {}
+
+        rebuild(&opts, drafts, &output, &version);
+    }
+
+    Ok(())
+}
+
+fn is_relevant(event: &notify::Event, cache_dir: &Path) -> bool {
+    let kind = &event.kind;
+    (kind.is_create() || kind.is_modify() || kind.is_remove())
+        && !event.paths.iter().all(|path| path.starts_with(cache_dir))
+}
+
+fn rebuild(opts: &Opts, drafts: bool, output: &Path, version: &AtomicU64) {
+    match crate::generate(opts.clone(), drafts, output) {
+        Ok(()) => {
+            let version = version.fetch_add(1, Ordering::SeqCst) + 1;
+            println!("built version {version}");
+        }
+        Err(err) => eprintln!("error: {err:?}"),
+    }
+}
+
+fn respond(output: &Path, version: &AtomicU64, request: tiny_http::Request) -> Result<()> {
+    let url = request.url();
+    let url = percent_decode(url.split(['?', '#']).next().unwrap_or_default());
+    let version = version.load(Ordering::SeqCst);
+
+    if url == VERSION_PATH {
+        let response = tiny_http::Response::from_string(version.to_string());
+        return request.respond(response).wrap_err("sending response");
+    }
+
+    let content = resolve(output, &url).and_then(|path| Some((std::fs::read(&path).ok()?, path)));
+    let Some((mut content, path)) = content else {
+        let response = tiny_http::Response::from_string("not found").with_status_code(404);
+        return request.respond(response).wrap_err("sending response");
+    };
+
+    let content_type = content_type(&path);
+    if content_type.starts_with("text/html") {
+        let mut html = String::from_utf8(content).wrap_err("page is not valid UTF-8")?;
+        let position = html.rfind("</body>").unwrap_or(html.len());
+        html.insert_str(position, &live_reload_script(version));
+        content = html.into_bytes();
+    }
+
+    let header =
+        tiny_http::Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap();
+    let response = tiny_http::Response::from_data(content).with_header(header);
+    request.respond(response).wrap_err("sending response")
+}
+
+/// Maps a URL path to a file in the output directory, refusing to leave it.
+fn resolve(output: &Path, url: &str) -> Option<PathBuf> {
+    let mut path = output.to_owned();
+    for component in Path::new(url.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if path.is_dir() {
+        path.push("index.html");
+    }
+    Some(path)
+}
+
+fn percent_decode(url: &str) -> String {
+    let mut bytes = Vec::with_capacity(url.len());
+    let mut rest = url.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let decoded = (byte == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match decoded {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("xml") => "application/xml; charset=utf-8",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("ico") => "image/x-icon",
+        Some("woff2") => "font/woff2",
+        Some("woff") => "font/woff",
+        Some("pdf") => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+fn live_reload_script(version: u64) -> String {
+    format!(
+        r#"<script>
+(() => {{
+  const version = "{version}";
+  setInterval(async () => {{
+    try {{
+      const response = await fetch("{VERSION_PATH}");
+      if ((await response.text()) !== version) {{
+        location.reload();
+      }}
+    }} catch {{}}
+  }}, 500);
+}})();
+</script>
+"#
+    )
+}
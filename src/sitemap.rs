@@ -0,0 +1,39 @@
+//! `sitemap.xml` and `robots.txt` for search engines.
+
+use crate::PostDate;
+use askama::Template;
+use color_eyre::{Result, eyre::WrapErr};
+
+/// A page listed in the sitemap.
+pub(crate) struct SitemapEntry {
+    /// The absolute path of the page, like `/blog/posts/hello/`.
+    path: String,
+    lastmod: Option<String>,
+}
+
+impl SitemapEntry {
+    pub(crate) fn new(path: String, lastmod: Option<PostDate>) -> Self {
+        Self {
+            path,
+            lastmod: lastmod.map(|date| date.iso8601()),
+        }
+    }
+}
+
+pub(crate) fn render_sitemap(base_url: &str, entries: &[SitemapEntry]) -> Result<String> {
+    #[derive(askama::Template)]
+    #[template(path = "../templates/sitemap.xml")]
+    struct SitemapTemplate<'a> {
+        base_url: &'a str,
+        entries: &'a [SitemapEntry],
+    }
+
+    SitemapTemplate { base_url, entries }
+        .render()
+        .wrap_err("failed to render sitemap template")
+}
+
+/// Allows everything and points to the sitemap.
+pub(crate) fn render_robots(base_url: &str) -> String {
+    format!("User-agent: *\nAllow: /\n\nSitemap: {base_url}/sitemap.xml\n")
+}
@@ -0,0 +1,71 @@
+//! Files shipped with the site as they are, like a favicon or fonts.
+//!
+//! Everything in `static/` (or `public/`) in the input directory is copied into the root
+//! of the output, unless `fingerprint_static` is set. Then the files are added to the
+//! content-hashed static files instead and are available to templates as `assets`.
+
+use crate::Context;
+use color_eyre::{
+    Result,
+    eyre::{OptionExt, WrapErr, bail},
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+const DIRS: &[&str] = &["static", "public"];
+
+/// Finds the directory with the static files in the input directory, if there is one.
+pub(crate) fn find_dir(input: &Path) -> Result<Option<PathBuf>> {
+    let mut found = DIRS
+        .iter()
+        .map(|name| input.join(name))
+        .filter(|dir| dir.is_dir());
+    let dir = found.next();
+    if let Some(other) = found.next() {
+        bail!(
+            "both {} and {} exist, only one static directory is supported",
+            dir.unwrap().display(),
+            other.display()
+        );
+    }
+    Ok(dir)
+}
+
+/// All files in the directory and its subdirectories, by their `/`-separated path in it.
+pub(crate) fn files(dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut files = vec![];
+    files_in(dir, "", &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn files_in(dir: &Path, prefix: &str, files: &mut Vec<(String, PathBuf)>) -> Result<()> {
+    let entries = std::fs::read_dir(dir).wrap_err_with(|| format!("reading {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let name = entry
+            .file_name()
+            .into_string()
+            .ok()
+            .ok_or_eyre("static file does not have a valid name")?;
+        let relative = format!("{prefix}{name}");
+
+        if entry.file_type()?.is_dir() {
+            files_in(&entry.path(), &format!("{relative}/"), files)?;
+        } else {
+            files.push((relative, entry.path()));
+        }
+    }
+    Ok(())
+}
+
+/// Adds all files in the directory to the static files, returning their URLs by their path in it.
+pub(crate) fn fingerprint(ctx: &Context, dir: &Path) -> Result<BTreeMap<String, String>> {
+    let mut urls = BTreeMap::new();
+    for (relative, path) in files(dir)? {
+        urls.insert(relative, ctx.add_file(&path)?);
+    }
+    Ok(urls)
+}
@@ -0,0 +1,125 @@
+//! Tags and the per-tag archive pages.
+
+use crate::{
+    Context, Post, slugify,
+    templates::{PostSummary, SiteContext},
+};
+use color_eyre::Result;
+use std::collections::BTreeMap;
+
+pub(crate) struct Tag<'a> {
+    name: &'a str,
+    pub(crate) slug: String,
+    pub(crate) posts: Vec<&'a Post>,
+    /// The font size in the tag cloud, from 100% to 200% for the most used tag.
+    size_percent: usize,
+}
+
+/// A link to a tag page from a post.
+#[derive(serde::Serialize)]
+pub(crate) struct TagLink<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) slug: String,
+}
+
+pub(crate) fn tag_links(post: &Post) -> Vec<TagLink<'_>> {
+    post.frontmatter
+        .tags
+        .iter()
+        .map(|name| TagLink {
+            name,
+            slug: slugify(name),
+        })
+        .collect()
+}
+
+/// Groups the posts by tag, keeping their order. Tags with the same slug are merged.
+pub(crate) fn collect_tags(posts: &[Post]) -> Vec<Tag<'_>> {
+    let mut tags = BTreeMap::<String, Tag<'_>>::new();
+
+    for post in posts {
+        for name in &post.frontmatter.tags {
+            let slug = slugify(name);
+            let tag = tags.entry(slug.clone()).or_insert_with(|| Tag {
+                name,
+                slug,
+                posts: vec![],
+                size_percent: 100,
+            });
+            if tag
+                .posts
+                .last()
+                .is_none_or(|last| !std::ptr::eq(*last, post))
+            {
+                tag.posts.push(post);
+            }
+        }
+    }
+
+    let mut tags = tags.into_values().collect::<Vec<_>>();
+
+    let max_count = tags.iter().map(|tag| tag.posts.len()).max().unwrap_or(0);
+    if max_count > 1 {
+        for tag in &mut tags {
+            tag.size_percent = 100 + 100 * (tag.posts.len() - 1) / (max_count - 1);
+        }
+    }
+
+    tags
+}
+
+pub(crate) fn render_tag(ctx: &Context, tag: &Tag<'_>) -> Result<String> {
+    #[derive(serde::Serialize)]
+    struct TagTemplate<'a> {
+        title: &'a str,
+        posts: Vec<PostSummary<'a>>,
+        site: SiteContext<'a>,
+        stylesheets: &'a [String],
+    }
+
+    let template = TagTemplate {
+        title: &format!("Posts tagged #{}", tag.name),
+        posts: tag
+            .posts
+            .iter()
+            .map(|post| PostSummary::new(post))
+            .collect(),
+        site: SiteContext::new(&ctx.site),
+        stylesheets: &ctx.stylesheets,
+    };
+    ctx.templates.render("tag.html", template)
+}
+
+pub(crate) fn render_tags(ctx: &Context, tags: &[Tag<'_>]) -> Result<String> {
+    #[derive(serde::Serialize)]
+    struct TagsTemplate<'a> {
+        title: &'a str,
+        tags: Vec<TagEntry<'a>>,
+        site: SiteContext<'a>,
+        stylesheets: &'a [String],
+    }
+
+    #[derive(serde::Serialize)]
+    struct TagEntry<'a> {
+        name: &'a str,
+        slug: &'a str,
+        count: usize,
+        size_percent: usize,
+    }
+
+    let template = TagsTemplate {
+        title: "Tags",
+        tags: tags
+            .iter()
+            .map(|tag| TagEntry {
+                name: tag.name,
+                slug: &tag.slug,
+                count: tag.posts.len(),
+                size_percent: tag.size_percent,
+            })
+            .collect(),
+        site: SiteContext::new(&ctx.site),
+        stylesheets: &ctx.stylesheets,
+    };
+    ctx.templates.render("tags.html", template)
+}
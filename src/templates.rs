@@ -0,0 +1,146 @@
+//! The HTML templates, rendered at runtime so they can be overridden by putting a file
+//! with the same name into `templates/` in the input directory or by using a theme.
+
+use crate::{PostDate, SiteConfig};
+use color_eyre::{
+    Result,
+    eyre::{WrapErr, eyre},
+};
+use std::{io, path::PathBuf};
+
+/// The built-in default theme, used for everything that isn't overridden.
+const DEFAULTS: &[(&str, &str)] = &[
+    ("layout.html", include_str!("../themes/default/layout.html")),
+    ("post.html", include_str!("../themes/default/post.html")),
+    ("index.html", include_str!("../themes/default/index.html")),
+    (
+        "post-list.html",
+        include_str!("../themes/default/post-list.html"),
+    ),
+    ("tag.html", include_str!("../themes/default/tag.html")),
+    ("tags.html", include_str!("../themes/default/tags.html")),
+    ("page.html", include_str!("../themes/default/page.html")),
+    ("theme.css", include_str!("../themes/default/theme.css")),
+];
+
+pub(crate) struct Templates {
+    /// Searched in order before falling back to the defaults.
+    dirs: Vec<PathBuf>,
+    env: minijinja::Environment<'static>,
+}
+
+impl Templates {
+    pub(crate) fn new(dirs: Vec<PathBuf>) -> Self {
+        let mut env = minijinja::Environment::new();
+        let loader_dirs = dirs.clone();
+        env.set_loader(move |name| {
+            let content = read(&loader_dirs, name).map(|found| found.map(|(content, _)| content));
+            content.map_err(|err| {
+                minijinja::Error::new(
+                    minijinja::ErrorKind::InvalidOperation,
+                    format!("failed to load template {name}"),
+                )
+                .with_source(err)
+            })
+        });
+        Self { dirs, env }
+    }
+
+    /// Makes a value available to all templates.
+    pub(crate) fn add_global(&mut self, name: &'static str, value: impl serde::Serialize) {
+        self.env
+            .add_global(name, minijinja::Value::from_serialize(value));
+    }
+
+    pub(crate) fn render(&self, name: &str, context: impl serde::Serialize) -> Result<String> {
+        self.env
+            .get_template(name)
+            .and_then(|template| template.render(context))
+            .wrap_err_with(|| format!("failed to render template {name}"))
+    }
+
+    /// Reads a non-template file like `theme.css`, together with the directory it was found
+    /// in, `None` for the built-in one.
+    pub(crate) fn asset(&self, name: &str) -> Result<(String, Option<PathBuf>)> {
+        read(&self.dirs, name)
+            .wrap_err_with(|| format!("reading {name}"))?
+            .ok_or_else(|| eyre!("no template asset named {name}"))
+    }
+}
+
+fn read(dirs: &[PathBuf], name: &str) -> io::Result<Option<(String, Option<PathBuf>)>> {
+    // Names come from our code and `extends`/`include` in the templates, don't leave the directory.
+    if name.split(['/', '\\']).any(|part| part == "..") {
+        return Ok(None);
+    }
+    for dir in dirs {
+        match std::fs::read_to_string(dir.join(name)) {
+            Ok(content) => return Ok(Some((content, Some(dir.clone())))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(DEFAULTS
+        .iter()
+        .find(|(default, _)| *default == name)
+        .map(|(_, content)| ((*content).to_owned(), None)))
+}
+
+/// The parts of the config available to templates as `site`.
+#[derive(serde::Serialize)]
+pub(crate) struct SiteContext<'a> {
+    title: &'a str,
+    author: &'a str,
+    nav_title: &'a str,
+    language: &'a str,
+    base_url: Option<&'a str>,
+}
+
+impl<'a> SiteContext<'a> {
+    pub(crate) fn new(site: &'a SiteConfig) -> Self {
+        Self {
+            title: &site.title,
+            author: site.author(),
+            nav_title: site.nav_title(),
+            language: &site.language,
+            base_url: site.base_url.as_deref(),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct DateContext {
+    iso8601: String,
+    rfc3339: String,
+    human: String,
+}
+
+impl DateContext {
+    pub(crate) fn new(date: &PostDate) -> Self {
+        Self {
+            iso8601: date.iso8601(),
+            rfc3339: date.rfc3339(),
+            human: date.human(),
+        }
+    }
+}
+
+/// A post in a list of posts, like on the index page.
+#[derive(serde::Serialize)]
+pub(crate) struct PostSummary<'a> {
+    name: &'a str,
+    title: &'a str,
+    date: DateContext,
+    draft: bool,
+}
+
+impl<'a> PostSummary<'a> {
+    pub(crate) fn new(post: &'a crate::Post) -> Self {
+        Self {
+            name: &post.name,
+            title: &post.frontmatter.common.title,
+            date: DateContext::new(&post.date),
+            draft: post.frontmatter.common.draft,
+        }
+    }
+}
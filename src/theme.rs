@@ -0,0 +1,69 @@
+//! Themes, bundling templates, CSS, fonts and other assets to share them between sites.
+//!
+//! A theme is a directory in `themes/` in the input directory, selected with `theme` in
+//! `blogamer.toml`. It contains any of the templates and `theme.css`, the missing ones are
+//! taken from the built-in `default` theme. Files in its `static/` directory are added to the
+//! static files and are available to templates as `assets`, keyed by their path inside
+//! `static/`, like `{{ assets["logo.png"] }}`.
+
+use crate::{Context, static_files};
+use color_eyre::{
+    Result,
+    eyre::{WrapErr, ensure},
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+pub(crate) const DEFAULT: &str = "default";
+
+/// Finds the directory of the theme, `None` for the built-in default theme.
+pub(crate) fn theme_dir(input: &Path, name: &str) -> Result<Option<PathBuf>> {
+    let dir = input.join("themes").join(name);
+    if dir.is_dir() {
+        return Ok(Some(dir));
+    }
+    ensure!(
+        name == DEFAULT,
+        "theme `{name}` does not exist, expected a directory at {}",
+        dir.display()
+    );
+    Ok(None)
+}
+
+/// Adds all files in the theme's `static/` directory, returning their URLs by their path in it.
+pub(crate) fn add_assets(ctx: &Context, theme_dir: &Path) -> Result<BTreeMap<String, String>> {
+    let static_dir = theme_dir.join("static");
+    if !static_dir.is_dir() {
+        return Ok(BTreeMap::new());
+    }
+    static_files::fingerprint(ctx, &static_dir)
+}
+
+/// Replaces relative `url()`s in CSS with the URLs of the static files they point to,
+/// like fonts shipped with the theme.
+pub(crate) fn rewrite_css_urls(ctx: &Context, css: &str, relative_to: &Path) -> Result<String> {
+    let mut rewritten = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("url(") {
+        let start = start + "url(".len();
+        let Some(len) = rest[start..].find(')') else {
+            break;
+        };
+        rewritten.push_str(&rest[..start]);
+
+        let url = &rest[start..][..len];
+        let dest = url.trim().trim_matches(['"', '\'']);
+        match ctx
+            .add_linked_file(relative_to, dest)
+            .wrap_err_with(|| format!("adding {dest} from CSS"))?
+        {
+            Some(new) => rewritten.push_str(&format!("\"{new}\"")),
+            None => rewritten.push_str(url),
+        }
+        rest = &rest[start + len..];
+    }
+    rewritten.push_str(rest);
+    Ok(rewritten)
+}
@@ -0,0 +1,84 @@
+//! Writing the output directory.
+//!
+//! Instead of clearing the output on every build, files are only written when their
+//! contents changed, and files that were not produced by the current build are removed
+//! at the end.
+
+use color_eyre::{
+    Result,
+    eyre::{WrapErr, ensure},
+};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+pub(crate) struct Output {
+    base: PathBuf,
+    written: HashSet<PathBuf>,
+}
+
+impl Output {
+    pub(crate) fn new(base: &Path) -> Result<Self> {
+        std::fs::create_dir_all(base).wrap_err("creating output")?;
+        Ok(Self {
+            base: base.to_owned(),
+            written: HashSet::new(),
+        })
+    }
+
+    /// Writes a file relative to the output directory, unless it already has the contents.
+    pub(crate) fn write(&mut self, relative: impl AsRef<Path>, content: &[u8]) -> Result<()> {
+        let path = self.base.join(relative);
+        ensure!(
+            !self.written.contains(&path),
+            "{} was already written by this build, two outputs collide",
+            path.display()
+        );
+
+        if std::fs::read(&path).is_ok_and(|existing| existing == content) {
+            self.written.insert(path);
+            return Ok(());
+        }
+
+        std::fs::create_dir_all(path.parent().unwrap())
+            .wrap_err_with(|| format!("creating {}", path.parent().unwrap().display()))?;
+        std::fs::write(&path, content).wrap_err_with(|| format!("writing {}", path.display()))?;
+        self.written.insert(path);
+        Ok(())
+    }
+
+    /// Removes all files from previous builds that were not written by this one.
+    pub(crate) fn finish(self) -> Result<()> {
+        self.remove_stale(&self.base)?;
+        Ok(())
+    }
+
+    /// Returns whether the directory is empty after the removal.
+    fn remove_stale(&self, dir: &Path) -> Result<bool> {
+        let mut empty = true;
+
+        for entry in
+            std::fs::read_dir(dir).wrap_err_with(|| format!("reading {}", dir.display()))?
+        {
+            let entry = entry?;
+            let path = entry.path();
+
+            if entry.file_type()?.is_dir() {
+                if self.remove_stale(&path)? {
+                    std::fs::remove_dir(&path)
+                        .wrap_err_with(|| format!("removing {}", path.display()))?;
+                } else {
+                    empty = false;
+                }
+            } else if self.written.contains(&path) {
+                empty = false;
+            } else {
+                std::fs::remove_file(&path)
+                    .wrap_err_with(|| format!("removing {}", path.display()))?;
+            }
+        }
+
+        Ok(empty)
+    }
+}